@@ -0,0 +1,115 @@
+//!
+//! Activation functions for nodes that pass their weighted sum through a non-linearity
+//!
+
+use std::f64;
+
+/// sqrt(2 / pi), used by the tanh approximation of GELU
+const GELU_COEFF: f64 = 0.797_884_560_802_865_4;
+
+/// An activation function `f`, applied on the weighted sum `z` of a node's inputs,
+/// i.e. `activation = f(z)`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Activation {
+    /// f(z) = z
+    Identity,
+    /// f(z) = 1 / (1 + e^-z)
+    Sigmoid,
+    /// f(z) = max(0, z)
+    Relu,
+    /// f(z) = z if z > 0, otherwise alpha * z
+    LeakyRelu(f64),
+    /// f(z) = tanh(z)
+    Tanh,
+    /// f(z) = z if z > 0, otherwise alpha * (e^z - 1)
+    Elu(f64),
+    /// f(z) = ln(1 + e^z)
+    Softplus,
+    /// Gaussian Error Linear Unit, using the tanh approximation:
+    /// f(z) = 0.5z(1 + tanh(sqrt(2/pi)(z + 0.044715z^3)))
+    Gelu,
+    /// f(z) = z * sigmoid(z)
+    Swish,
+}
+
+fn sigmoid(z: f64) -> f64 {
+    1.0 / (1.0 + f64::exp(-z))
+}
+
+impl Activation {
+    /// Calculates f(z)
+    pub fn value(&self, z: f64) -> f64 {
+        match *self {
+            Activation::Identity => z,
+            Activation::Sigmoid => sigmoid(z),
+            Activation::Relu => z.max(0.0),
+            Activation::LeakyRelu(alpha) => {
+                if z > 0.0 {
+                    z
+                } else {
+                    alpha * z
+                }
+            }
+            Activation::Tanh => z.tanh(),
+            Activation::Elu(alpha) => {
+                if z > 0.0 {
+                    z
+                } else {
+                    alpha * z.exp_m1()
+                }
+            }
+            // Rearranged so that large values of z don't overflow e^z
+            Activation::Softplus => z.max(0.0) + (-z.abs()).exp().ln_1p(),
+            Activation::Gelu => 0.5 * z * (1.0 + (GELU_COEFF * (z + 0.044_715 * z.powi(3))).tanh()),
+            Activation::Swish => z * sigmoid(z),
+        }
+    }
+
+    /// Calculates f'(z), i.e. d(activation) / d(weighted sum)
+    pub fn derivative(&self, z: f64) -> f64 {
+        match *self {
+            Activation::Identity => 1.0,
+            Activation::Sigmoid => {
+                let a = sigmoid(z);
+                a * (1.0 - a)
+            }
+            Activation::Relu => {
+                if z > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Activation::LeakyRelu(alpha) => {
+                if z > 0.0 {
+                    1.0
+                } else {
+                    alpha
+                }
+            }
+            Activation::Tanh => 1.0 - z.tanh().powi(2),
+            Activation::Elu(alpha) => {
+                if z > 0.0 {
+                    1.0
+                } else {
+                    alpha * z.exp()
+                }
+            }
+            Activation::Softplus => sigmoid(z),
+            Activation::Gelu => {
+                // let u = sqrt(2/pi)(z + 0.044715z^3)
+                // f(z) = 0.5z(1 + tanh(u))
+                // f'(z) = 0.5(1 + tanh(u)) + 0.5z(1 - tanh(u)^2) * du/dz
+                let u = GELU_COEFF * (z + 0.044_715 * z.powi(3));
+                let du_dz = GELU_COEFF * (1.0 + 3.0 * 0.044_715 * z.powi(2));
+                let tanh_u = u.tanh();
+
+                0.5 * (1.0 + tanh_u) + 0.5 * z * (1.0 - tanh_u.powi(2)) * du_dz
+            }
+            Activation::Swish => {
+                let s = sigmoid(z);
+                s + z * s * (1.0 - s)
+            }
+        }
+    }
+}
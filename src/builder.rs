@@ -0,0 +1,170 @@
+//!
+//! Declarative construction of fully-connected networks
+//!
+
+use activation::Activation;
+use graph::{Graph, NodeId};
+use init::Initializer;
+use layers::{InputLayer, OutputLayer};
+use loss::{Loss, MeanSquaredError};
+use network::{Network, NetworkConfigs};
+use node::{ActivationNode, ConstantNode, InputNode};
+
+/// Builds a fully-connected feed-forward `Network`, creating and connecting all the nodes.
+///
+/// ```
+/// # use neural_network::activation::Activation;
+/// # use neural_network::network::Network;
+/// # let training_vals = vec![0.0; 784];
+/// # let ground_truths = vec![0.0; 10];
+/// let network = Network::builder()
+///     .input(784)
+///     .dense(128, Activation::Relu)
+///     .dense(10, Activation::Sigmoid)
+///     .bias(true)
+///     .training_data(&training_vals, &ground_truths)
+///     .build();
+/// ```
+///
+/// Nodes are named `input_{i}` for the input layer, `dense{l}_{i}` for the `i`th node of
+/// the `l`th dense layer (starting from 1), and `dense{l}_bias` for the bias of each dense layer.
+pub struct NetworkBuilder {
+    input_count: usize,
+    /// (Number of nodes, activation function) of each dense layer, in order.
+    dense_layers: Vec<(usize, Activation)>,
+    bias: bool,
+    training_inputs: Vec<f64>,
+    training_ground_truths: Vec<f64>,
+    loss: Box<dyn Loss>,
+    network_configs: NetworkConfigs,
+}
+
+impl NetworkBuilder {
+    pub fn new() -> NetworkBuilder {
+        NetworkBuilder {
+            input_count: 0,
+            dense_layers: vec![],
+            bias: false,
+            training_inputs: vec![],
+            training_ground_truths: vec![],
+            loss: Box::new(MeanSquaredError),
+            network_configs: Default::default(),
+        }
+    }
+
+    /// Set the number of input nodes
+    pub fn input(mut self, node_count: usize) -> NetworkBuilder {
+        self.input_count = node_count;
+        self
+    }
+
+    /// Add a layer of `node_count` nodes which are fully connected to the previous layer.
+    ///
+    /// The last dense layer added becomes the output layer.
+    pub fn dense(mut self, node_count: usize, activation_fn: Activation) -> NetworkBuilder {
+        self.dense_layers.push((node_count, activation_fn));
+        self
+    }
+
+    /// Whether to connect a `ConstantNode` with a value of 1 as a bias to every dense layer.
+    /// Default: false
+    pub fn bias(mut self, bias: bool) -> NetworkBuilder {
+        self.bias = bias;
+        self
+    }
+
+    /// Flattened training inputs and ground truths, in the same format as
+    /// `InputLayer::new()` and `OutputLayer::new()`.
+    pub fn training_data(mut self, inputs: &[f64], ground_truths: &[f64]) -> NetworkBuilder {
+        self.training_inputs = inputs.to_vec();
+        self.training_ground_truths = ground_truths.to_vec();
+        self
+    }
+
+    /// Set the loss function, see `OutputLayer::new()`.
+    ///
+    /// Default: `MeanSquaredError`
+    pub fn loss(mut self, loss: Box<dyn Loss>) -> NetworkBuilder {
+        self.loss = loss;
+        self
+    }
+
+    pub fn network_configs(mut self, network_configs: NetworkConfigs) -> NetworkBuilder {
+        self.network_configs = network_configs;
+        self
+    }
+
+    /// Set the strategy used to initialize the weights of all connections.
+    /// This overrides `NetworkConfigs.initializer`.
+    pub fn initializer(mut self, initializer: Initializer) -> NetworkBuilder {
+        self.network_configs.initializer = initializer;
+        self
+    }
+
+    /// Create all nodes, connect each layer to the next, and assemble the `Network`.
+    ///
+    /// Weights are drawn with the configured `Initializer` once all layers are connected,
+    /// so that the fan-in and fan-out of every node is known.
+    pub fn build(self) -> Network {
+        assert!(self.input_count > 0, "Network must have at least 1 input!");
+        assert!(
+            !self.dense_layers.is_empty(),
+            "Network must have at least 1 dense layer!"
+        );
+
+        let mut graph = Graph::new();
+
+        let input_nodes: Vec<NodeId> = (0..self.input_count)
+            .map(|i| InputNode::new(&mut graph, &format!("input_{}", i), 0.0))
+            .collect();
+
+        let mut prev_layer = input_nodes.clone();
+
+        for (layer_idx, &(node_count, activation_fn)) in self.dense_layers.iter().enumerate() {
+            let layer_no = layer_idx + 1;
+
+            let bias = if self.bias {
+                Some(ConstantNode::new(
+                    &mut graph,
+                    &format!("dense{}_bias", layer_no),
+                    1.0,
+                ))
+            } else {
+                None
+            };
+
+            let mut layer = vec![];
+
+            for i in 0..node_count {
+                let node = ActivationNode::new(
+                    &mut graph,
+                    &format!("dense{}_{}", layer_no, i),
+                    activation_fn,
+                );
+
+                for &prev in &prev_layer {
+                    graph.connect(prev, node);
+                }
+
+                if let Some(bias) = bias {
+                    graph.connect(bias, node);
+                }
+
+                layer.push(node);
+            }
+
+            prev_layer = layer;
+        }
+
+        let input_layer = InputLayer::new(&input_nodes, &self.training_inputs);
+        let output_layer = OutputLayer::new(&prev_layer, &self.training_ground_truths, self.loss);
+
+        Network::with_configs(graph, input_layer, output_layer, self.network_configs)
+    }
+}
+
+impl Default for NetworkBuilder {
+    fn default() -> NetworkBuilder {
+        NetworkBuilder::new()
+    }
+}
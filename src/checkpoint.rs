@@ -0,0 +1,452 @@
+//!
+//! Compact binary checkpoints of training progress, see `Network.checkpoint()` and
+//! `Network.resume()`
+//!
+
+use activation::Activation;
+use graph::{Graph, NodeId};
+use network::ClipStats;
+use node::{check_structure, create_nodes, invalid_data, NodeKind};
+use optimizer::OptimizerState;
+use std::io;
+
+/// Identifies checkpoint files
+const MAGIC: &[u8; 4] = b"RBCK";
+
+/// Written after `MAGIC`. `decode()` only reads checkpoints of this version, so it's bumped
+/// whenever the layout written by `encode()` changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The training progress of a `Network` stored in a checkpoint besides its graph.
+#[derive(Debug, Clone, Default)]
+pub struct TrainingProgress {
+    /// `NetworkConfigs.seed`, which along with `epoch` determines the state of the RNG
+    pub seed: u64,
+    pub epoch: usize,
+    pub iteration: usize,
+    pub batch_samples: usize,
+    pub weight_updates: usize,
+    pub gradient_checks: usize,
+    pub clip_stats: ClipStats,
+    /// `LearningRateSchedule.state()`
+    pub schedule_state: Vec<f64>,
+}
+
+/// Writes the nodes of `graph` along with their `TrainingState`, every `NodeWeight` including
+/// its accumulated gradient and optimizer state, the input and output nodes in order,
+/// and `progress`.
+///
+/// All numbers are little-endian. Nodes are referred to by their position in the checkpoint.
+pub fn encode(
+    graph: &Graph,
+    input_nodes: &[NodeId],
+    output_nodes: &[NodeId],
+    progress: &TrainingProgress,
+) -> Vec<u8> {
+    let mut w = Writer { bytes: vec![] };
+
+    w.bytes.extend_from_slice(MAGIC);
+    w.u32(FORMAT_VERSION);
+
+    w.u64(progress.seed);
+    w.usize(progress.epoch);
+    w.usize(progress.iteration);
+    w.usize(progress.batch_samples);
+    w.usize(progress.weight_updates);
+    w.usize(progress.gradient_checks);
+    w.usize(progress.clip_stats.clipped_values);
+    w.usize(progress.clip_stats.clipped_norms);
+    w.usize(progress.schedule_state.len());
+    for &x in &progress.schedule_state {
+        w.f64(x);
+    }
+
+    // Removed nodes leave gaps in the ids, so map ids to positions in the checkpoint
+    let ids = graph.node_ids();
+    let mut positions = vec![0; ids.last().map_or(0, |id| id.index() + 1)];
+    for (position, id) in ids.iter().enumerate() {
+        positions[id.index()] = position;
+    }
+    let position = |id: NodeId| positions[id.index()];
+
+    w.usize(ids.len());
+    for &id in &ids {
+        let node = graph.node(id);
+        w.str(node.name());
+
+        match node.kind() {
+            NodeKind::Input(value) => {
+                w.u8(0);
+                w.f64(value);
+            }
+            NodeKind::Constant(value) => {
+                w.u8(1);
+                w.f64(value);
+            }
+            NodeKind::Sum => w.u8(2),
+            NodeKind::Sigmoid => w.u8(3),
+            NodeKind::Activation(activation_fn) => {
+                w.u8(4);
+                w.activation(activation_fn);
+            }
+            NodeKind::Softmax {
+                logit,
+                fused_cross_entropy,
+            } => {
+                w.u8(5);
+                w.usize(position(logit));
+                w.bool(fused_cross_entropy);
+            }
+        }
+
+        let training_state = node.get_training_state();
+        w.i32(training_state.calc_derivative_iteration);
+        w.f64(training_state.dloss);
+    }
+
+    for &id in &ids {
+        let input_weights = graph.input_weights(id);
+
+        w.usize(input_weights.len());
+        for nw in input_weights {
+            w.usize(position(nw.node));
+            w.f64(nw.weight);
+            w.bool(nw.initialized);
+            w.f64(nw.gradient);
+            w.f64(nw.optimizer_state.velocity);
+            w.f64(nw.optimizer_state.first_moment);
+            w.f64(nw.optimizer_state.second_moment);
+            w.u64(nw.optimizer_state.step);
+        }
+    }
+
+    for nodes in &[input_nodes, output_nodes] {
+        w.usize(nodes.len());
+        for &id in nodes.iter() {
+            w.usize(position(id));
+        }
+    }
+
+    w.bytes
+}
+
+/// Reads a checkpoint written by `encode()`.
+///
+/// Returns the graph, the input and output nodes in order, and the training progress.
+/// Errors with `InvalidData` on the same malformed graphs as `model::from_json()`.
+pub fn decode(bytes: &[u8]) -> io::Result<(Graph, Vec<NodeId>, Vec<NodeId>, TrainingProgress)> {
+    let mut r = Reader { bytes, pos: 0 };
+
+    if r.take(4)? != MAGIC {
+        return Err(invalid_data("Not a checkpoint file".to_string()));
+    }
+    let version = r.u32()?;
+    if version != FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported checkpoint format version {}, expected {}",
+            version, FORMAT_VERSION
+        )));
+    }
+
+    let mut progress = TrainingProgress {
+        seed: r.u64()?,
+        epoch: r.usize()?,
+        iteration: r.usize()?,
+        batch_samples: r.usize()?,
+        weight_updates: r.usize()?,
+        gradient_checks: r.usize()?,
+        clip_stats: ClipStats {
+            clipped_values: r.usize()?,
+            clipped_norms: r.usize()?,
+        },
+        schedule_state: vec![],
+    };
+    for _ in 0..r.count()? {
+        progress.schedule_state.push(r.f64()?);
+    }
+
+    let node_count = r.count()?;
+    let mut records = Vec::with_capacity(node_count);
+    // (calc_derivative_iteration, dloss) of each node
+    let mut training_states = Vec::with_capacity(node_count);
+
+    for _ in 0..node_count {
+        let name = r.str()?;
+
+        let kind = match r.u8()? {
+            0 => NodeKind::Input(r.f64()?),
+            1 => NodeKind::Constant(r.f64()?),
+            2 => NodeKind::Sum,
+            3 => NodeKind::Sigmoid,
+            4 => NodeKind::Activation(r.activation()?),
+            5 => NodeKind::Softmax {
+                logit: r.position(node_count)?,
+                fused_cross_entropy: r.bool()?,
+            },
+            tag => return Err(invalid_data(format!("Unknown node kind {}", tag))),
+        };
+
+        records.push((name, kind));
+        training_states.push((r.i32()?, r.f64()?));
+    }
+
+    let mut graph = Graph::new();
+    let ids = create_nodes(&mut graph, &records)?;
+
+    for (&id, &(calc_derivative_iteration, dloss)) in ids.iter().zip(training_states.iter()) {
+        let training_state = graph.node_mut(id).get_training_state_mut();
+        training_state.calc_derivative_iteration = calc_derivative_iteration;
+        training_state.dloss = dloss;
+    }
+
+    for &id in &ids {
+        for _ in 0..r.count()? {
+            let input_node = ids[r.position(node_count)?];
+            graph.connect_init(input_node, id, r.f64()?);
+
+            let nw = graph.input_weights_mut(id).last_mut().unwrap();
+            nw.initialized = r.bool()?;
+            nw.gradient = r.f64()?;
+            nw.optimizer_state = OptimizerState {
+                velocity: r.f64()?,
+                first_moment: r.f64()?,
+                second_moment: r.f64()?,
+                step: r.u64()?,
+            };
+        }
+    }
+
+    let mut layers = vec![];
+    for _ in 0..2 {
+        let mut nodes = vec![];
+        for _ in 0..r.count()? {
+            nodes.push(ids[r.position(node_count)?]);
+        }
+        layers.push(nodes);
+    }
+    let output_nodes = layers.pop().unwrap();
+    let input_nodes = layers.pop().unwrap();
+
+    if r.pos != bytes.len() {
+        return Err(invalid_data(
+            "Unexpected data at the end of the checkpoint".to_string(),
+        ));
+    }
+
+    check_structure(&graph, &input_nodes)?;
+
+    Ok((graph, input_nodes, output_nodes, progress))
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    fn bool(&mut self, x: bool) {
+        self.u8(x as u8);
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn i32(&mut self, x: i32) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn usize(&mut self, x: usize) {
+        self.u64(x as u64);
+    }
+
+    fn f64(&mut self, x: f64) {
+        self.u64(x.to_bits());
+    }
+
+    fn str(&mut self, x: &str) {
+        self.usize(x.len());
+        self.bytes.extend_from_slice(x.as_bytes());
+    }
+
+    /// The tag of the activation function followed by its parameter, or 0 if it has none
+    fn activation(&mut self, activation_fn: Activation) {
+        let (tag, param) = match activation_fn {
+            Activation::Identity => (0, 0.0),
+            Activation::Sigmoid => (1, 0.0),
+            Activation::Relu => (2, 0.0),
+            Activation::LeakyRelu(alpha) => (3, alpha),
+            Activation::Tanh => (4, 0.0),
+            Activation::Elu(alpha) => (5, alpha),
+            Activation::Softplus => (6, 0.0),
+            Activation::Gelu => (7, 0.0),
+            Activation::Swish => (8, 0.0),
+        };
+
+        self.u8(tag);
+        self.f64(param);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Checkpoint ended unexpectedly",
+            ));
+        }
+
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn usize(&mut self) -> io::Result<usize> {
+        Ok(self.u64()? as usize)
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    /// Number of elements that follow, which can't be more than the remaining bytes
+    fn count(&mut self) -> io::Result<usize> {
+        let count = self.usize()?;
+        if count > self.bytes.len() - self.pos {
+            return Err(invalid_data(format!("Invalid element count {}", count)));
+        }
+        Ok(count)
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let len = self.usize()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| invalid_data("Node name is not valid UTF-8".to_string()))
+    }
+
+    /// Position of a node in the checkpoint, which must be below `node_count`
+    fn position(&mut self, node_count: usize) -> io::Result<usize> {
+        let position = self.usize()?;
+        if position >= node_count {
+            return Err(invalid_data(format!("Unknown node position {}", position)));
+        }
+        Ok(position)
+    }
+
+    fn activation(&mut self) -> io::Result<Activation> {
+        let tag = self.u8()?;
+        let param = self.f64()?;
+
+        Ok(match tag {
+            0 => Activation::Identity,
+            1 => Activation::Sigmoid,
+            2 => Activation::Relu,
+            3 => Activation::LeakyRelu(param),
+            4 => Activation::Tanh,
+            5 => Activation::Elu(param),
+            6 => Activation::Softplus,
+            7 => Activation::Gelu,
+            8 => Activation::Swish,
+            tag => return Err(invalid_data(format!("Unknown activation function {}", tag))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use network::Network;
+    use std::fs;
+    use std::io;
+    use test_utils::{softmax_network, temp_path};
+
+    fn weight_bits(network: &Network) -> Vec<u64> {
+        let mut bits = vec![];
+        network
+            .graph
+            .visit_weights(|_, _, nw| bits.push(nw.weight.to_bits()));
+        bits
+    }
+
+    #[test]
+    fn resumed_training_matches_uninterrupted_training() {
+        let mut uninterrupted = softmax_network();
+        for _ in 0..3 {
+            uninterrupted.train_one_epoch();
+        }
+
+        let mut interrupted = softmax_network();
+        for _ in 0..2 {
+            interrupted.train_one_epoch();
+        }
+
+        let path = temp_path("checkpoint-test", "bin");
+        interrupted.checkpoint(&path).unwrap();
+
+        let mut resumed = softmax_network();
+        let result = resumed.resume(&path);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        resumed.train_one_epoch();
+
+        assert_eq!(weight_bits(&resumed), weight_bits(&uninterrupted));
+    }
+
+    #[test]
+    fn cyclic_checkpoint_is_invalid_data() {
+        let mut cyclic = softmax_network();
+        let softmax_0 = cyclic.graph.get_node_id("softmax_0").unwrap();
+        let l1 = cyclic.graph.get_node_id("l1").unwrap();
+        cyclic.graph.connect(softmax_0, l1);
+
+        let path = temp_path("cyclic-checkpoint-test", "bin");
+        cyclic.checkpoint(&path).unwrap();
+
+        let mut network = softmax_network();
+        let before = weight_bits(&network);
+        let result = network.resume(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(weight_bits(&network), before);
+    }
+}
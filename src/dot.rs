@@ -0,0 +1,172 @@
+//!
+//! Graphviz DOT export of the node graph, see `Network.to_dot()`
+//!
+
+use graph::{Graph, NodeId};
+use node::NodeKind;
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+/// What to show in addition to node names, node kinds and edge weights.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DotOptions {
+    /// Show the last calculated activation of each node.
+    /// Default: false
+    pub activations: bool,
+    /// Show the last d(loss)/d(activation) of each node, i.e. `TrainingState.dloss`.
+    /// Default: false
+    pub dloss: bool,
+}
+
+/// Writes the nodes reachable from `input_nodes` along with every node connected as their
+/// input (e.g. biases), as a DOT digraph.
+///
+/// Edges into weighted nodes are coloured by d(loss)/d(weight) of the last backward pass,
+/// i.e. `NodeWeight.last_gradient`: red for positive and blue for negative gradients, more
+/// saturated and thicker the larger the gradient is relative to the largest one. Edges
+/// without a gradient are grey. The activations and dloss values in the labels are the
+/// latest ones, which after e.g. `Network.evaluate()` are no longer those of the sample the
+/// gradients were calculated for.
+pub fn to_dot(
+    graph: &Graph,
+    input_nodes: &[NodeId],
+    output_nodes: &[NodeId],
+    options: DotOptions,
+) -> String {
+    // Breadth-first from the input nodes, so that the nodes are listed roughly by layer
+    let mut visited = vec![false; graph.node_ids().last().map_or(0, |id| id.index() + 1)];
+    let mut order = vec![];
+    let mut queue: VecDeque<NodeId> = VecDeque::new();
+
+    for &id in input_nodes {
+        if !visited[id.index()] {
+            visited[id.index()] = true;
+            queue.push_back(id);
+        }
+    }
+
+    while let Some(id) = queue.pop_front() {
+        order.push(id);
+
+        for &output in graph.output_nodes(id) {
+            if !visited[output.index()] {
+                visited[output.index()] = true;
+                queue.push_back(output);
+            }
+        }
+    }
+
+    // (input node, receiving node, weight, d(loss)/d(weight) if the node is weighted)
+    let mut edges = vec![];
+    for id in order.clone() {
+        let is_weighted = graph.node(id).is_weighted();
+
+        for nw in graph.input_weights(id) {
+            let gradient = if is_weighted {
+                Some(nw.last_gradient)
+            } else {
+                None
+            };
+            edges.push((nw.node, id, nw.weight, gradient));
+
+            // Nodes only connected as inputs, such as biases, aren't reached from the inputs
+            if !visited[nw.node.index()] {
+                visited[nw.node.index()] = true;
+                order.push(nw.node);
+            }
+        }
+    }
+
+    let max_gradient = edges
+        .iter()
+        .filter_map(|&(_, _, _, gradient)| gradient)
+        .fold(0.0, |max: f64, gradient| max.max(gradient.abs()));
+
+    let mut dot = String::new();
+    writeln!(dot, "digraph network {{").unwrap();
+    writeln!(dot, "    rankdir=LR;").unwrap();
+    writeln!(dot, "    node [shape=ellipse];").unwrap();
+
+    for &id in &order {
+        let node = graph.node(id);
+
+        let mut label = format!("{}\n{}", node.name(), kind_name(node.kind()));
+        if options.activations {
+            write!(label, "\na = {:.4}", node.get_last_calc_activation()).unwrap();
+        }
+        if options.dloss {
+            write!(label, "\ndloss = {:.4e}", node.get_training_state().dloss).unwrap();
+        }
+
+        let shape = if input_nodes.contains(&id) {
+            "box"
+        } else if output_nodes.contains(&id) {
+            "doublecircle"
+        } else if node.is_constant() {
+            "diamond"
+        } else {
+            "ellipse"
+        };
+
+        writeln!(
+            dot,
+            "    {} [label={}, shape={}];",
+            quote(node.name()),
+            quote(&label),
+            shape
+        )
+        .unwrap();
+    }
+
+    for &(from, to, weight, gradient) in &edges {
+        let (color, penwidth) = match gradient {
+            Some(gradient) if gradient != 0.0 && max_gradient > 0.0 => {
+                let magnitude = gradient.abs() / max_gradient;
+                // HSV: red for positive, blue for negative
+                let hue = if gradient > 0.0 { 0.0 } else { 0.667 };
+                (
+                    format!("{:.3} {:.3} 0.900", hue, 0.15 + 0.85 * magnitude),
+                    1.0 + 2.0 * magnitude,
+                )
+            }
+            _ => ("gray".to_string(), 1.0),
+        };
+
+        writeln!(
+            dot,
+            "    {} -> {} [label=\"{:.4}\", color={}, penwidth={:.2}];",
+            quote(graph.node(from).name()),
+            quote(graph.node(to).name()),
+            weight,
+            quote(&color),
+            penwidth
+        )
+        .unwrap();
+    }
+
+    writeln!(dot, "}}").unwrap();
+
+    dot
+}
+
+/// The name of the node's type, along with its activation function if any
+fn kind_name(kind: NodeKind) -> String {
+    match kind {
+        NodeKind::Input(_) => "InputNode".to_string(),
+        NodeKind::Constant(value) => format!("ConstantNode ({})", value),
+        NodeKind::Sum => "SumNode".to_string(),
+        NodeKind::Sigmoid => "SigmoidNode".to_string(),
+        NodeKind::Activation(activation_fn) => format!("ActivationNode ({:?})", activation_fn),
+        NodeKind::Softmax { .. } => "SoftmaxNode".to_string(),
+    }
+}
+
+/// Quotes a DOT ID, escaping quotes and backslashes, and line breaks as centered lines
+fn quote(id: &str) -> String {
+    let escaped = id
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+
+    format!("\"{}\"", escaped)
+}
@@ -0,0 +1,614 @@
+//!
+//! The arena storing all the nodes and connections of a single neural network graph
+//!
+
+use init::Initializer;
+use node::{
+    update_input_weights, DerivativeCalculationParams, Node, NodeInput, NodeKind, NodeWeight,
+    WeightUpdateParams,
+};
+use rand::Rng;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+/// Handle to a node stored in a `Graph`, as returned by the node constructors.
+///
+/// Only meaningful for the `Graph` that created it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// Position of the node in its `Graph`, in order of creation
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Arena owning all the nodes that make up one neural network graph, and the
+/// connections between them.
+///
+/// A `Graph` is passed to the constructors of nodes, which add themselves into it and return
+/// their `NodeId`. Node names only need to be unique within one `Graph`, so multiple networks
+/// can coexist in the same process.
+pub struct Graph {
+    /// Indexed by `NodeId`. Removed nodes leave an empty slot behind, so that the ids of
+    /// the other nodes stay valid.
+    nodes: Vec<Option<Box<dyn Node + Send>>>,
+    /// The input connections of each node in the order they were connected, indexed by `NodeId`
+    inputs: Vec<Vec<NodeWeight>>,
+    /// The nodes receiving each node's output, indexed by `NodeId`.
+    /// A node appears once per connection.
+    outputs: Vec<Vec<NodeId>>,
+    /// Node name: NodeId
+    names: HashMap<String, NodeId>,
+    /// Cached result of `topological_order()`, cleared whenever nodes or connections are added.
+    order: Option<Vec<NodeId>>,
+    /// Whether there may be connections whose weights are yet to be drawn by
+    /// `initialize_weights()`
+    pending_weights: bool,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph {
+            nodes: vec![],
+            inputs: vec![],
+            outputs: vec![],
+            names: HashMap::new(),
+            order: None,
+            pending_weights: false,
+        }
+    }
+
+    /// Call this in the constructor of nodes
+    pub fn add_node(&mut self, node: Box<dyn Node + Send>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        let name = node.name().to_string();
+
+        if self.names.insert(name.clone(), id).is_some() {
+            panic!("Cannot create two nodes with same name! [{}]", name);
+        }
+
+        self.nodes.push(Some(node));
+        self.inputs.push(vec![]);
+        self.outputs.push(vec![]);
+        self.order = None;
+
+        id
+    }
+
+    /// Retrieve the id of a node by its name
+    pub fn get_node_id(&self, name: &str) -> Option<NodeId> {
+        self.names.get(name).cloned()
+    }
+
+    pub fn contains_node(&self, name: &str) -> bool {
+        self.names.contains_key(name)
+    }
+
+    /// Panics if the node has been removed.
+    pub fn node(&self, id: NodeId) -> &(dyn Node + Send) {
+        match self.nodes[id.0] {
+            Some(ref node) => &**node,
+            None => panic!("Node {:?} has been removed from the graph!", id),
+        }
+    }
+
+    /// Panics if the node has been removed.
+    pub fn node_mut(&mut self, id: NodeId) -> &mut (dyn Node + Send) {
+        match self.nodes[id.0] {
+            Some(ref mut node) => &mut **node,
+            None => panic!("Node {:?} has been removed from the graph!", id),
+        }
+    }
+
+    /// The ids of all nodes in this graph, in order of creation.
+    pub fn node_ids(&self) -> Vec<NodeId> {
+        (0..self.nodes.len())
+            .filter(|&idx| self.nodes[idx].is_some())
+            .map(NodeId)
+            .collect()
+    }
+
+    /// Number of nodes in this graph
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Connect the output of node a to the input of node b.
+    ///
+    /// The weight of the connection is drawn by the `Initializer` in `NetworkConfigs`
+    /// when the `Network` is created, as only then the fan-in and fan-out of every node is known.
+    /// Connections made after that are drawn before the next forward pass, see
+    /// `Network.initialize_weights()`.
+    /// Connections into nodes that aren't weighted (see `Node.is_weighted()`) get a fixed
+    /// weight of 1 instead.
+    pub fn connect(&mut self, a: NodeId, b: NodeId) {
+        let node_weight = if self.node(b).is_weighted() {
+            self.pending_weights = true;
+            NodeWeight::uninitialized(a)
+        } else {
+            NodeWeight::new(a, 1.0)
+        };
+
+        self.outputs[a.0].push(b);
+        self.inputs[b.0].push(node_weight);
+        self.order = None;
+    }
+
+    /// Connect the output of node a to the input of node b with a preset weight.
+    pub fn connect_init(&mut self, a: NodeId, b: NodeId, weight: f64) {
+        self.outputs[a.0].push(b);
+        self.inputs[b.0].push(NodeWeight::new(a, weight));
+        self.order = None;
+    }
+
+    /// Removes every connection from the output of node a to the input of node b, along with
+    /// their weights.
+    ///
+    /// Returns whether a and b were connected.
+    ///
+    /// Panics if a is the logit of b, which is a `SoftmaxNode`.
+    pub fn disconnect(&mut self, a: NodeId, b: NodeId) -> bool {
+        assert!(
+            !self.is_logit_of(a, b),
+            "[{}] Cannot disconnect the logit of [{}]!",
+            self.node(a).name(),
+            self.node(b).name()
+        );
+
+        self.remove_connections(a, b)
+    }
+
+    fn remove_connections(&mut self, a: NodeId, b: NodeId) -> bool {
+        let connections = self.inputs[b.0].len();
+        self.inputs[b.0].retain(|nw| nw.node != a);
+        self.outputs[a.0].retain(|&output| output != b);
+
+        if self.inputs[b.0].len() == connections {
+            return false;
+        }
+
+        self.order = None;
+        true
+    }
+
+    /// Removes a node along with all of its input and output connections, and returns it.
+    /// Returns `None` if there is no node with that name.
+    ///
+    /// The ids of the other nodes are unaffected. The id of the removed node must not be used
+    /// anymore, so use `Network.remove_node()` once the graph is part of a `Network`, which
+    /// refuses to remove nodes of the `InputLayer` and `OutputLayer`.
+    ///
+    /// Panics if the node is the logit of a `SoftmaxNode`.
+    pub fn remove_node(&mut self, name: &str) -> Option<Box<dyn Node + Send>> {
+        let id = self.get_node_id(name)?;
+
+        for &output in &self.outputs[id.0] {
+            assert!(
+                !self.is_logit_of(id, output),
+                "[{}] Cannot remove the logit of [{}], remove the SoftmaxNode first!",
+                name,
+                self.node(output).name()
+            );
+        }
+
+        self.names.remove(name);
+
+        for a in self.input_nodes(id) {
+            self.remove_connections(a, id);
+        }
+        for b in self.outputs[id.0].clone() {
+            self.remove_connections(id, b);
+        }
+
+        self.order = None;
+        self.nodes[id.0].take()
+    }
+
+    /// Swaps the node named `old` for the node `new`, which takes over the id of `old` and
+    /// all of its input and output connections along with their weights, so that e.g. the
+    /// `OutputLayer` refers to `new` afterwards. Returns the old node, or `None` if there is
+    /// no node named `old`.
+    ///
+    /// `new` must be a node without any connections, as just created by its constructor.
+    /// Its own id must not be used anymore.
+    ///
+    /// If `new` isn't weighted (see `Node.is_weighted()`), the weights of its input
+    /// connections are set to 1 as with `connect()`. If `new` is weighted but the old node
+    /// wasn't, they are drawn by the `Initializer` as with `connect()` instead.
+    pub fn replace_node(&mut self, old: &str, new: NodeId) -> Option<Box<dyn Node + Send>> {
+        let id = self.get_node_id(old)?;
+
+        assert!(id != new, "[{}] Cannot replace a node with itself!", old);
+        assert!(
+            self.inputs[new.0].is_empty() && self.outputs[new.0].is_empty(),
+            "[{}] The replacement node must not have any connections!",
+            self.node(new).name()
+        );
+
+        let new_node = self.nodes[new.0].take().unwrap();
+        self.names.remove(old);
+        self.names.insert(new_node.name().to_string(), id);
+
+        if !new_node.is_weighted() {
+            for nw in &mut self.inputs[id.0] {
+                *nw = NodeWeight::new(nw.node, 1.0);
+            }
+        } else if !self.node(id).is_weighted() {
+            for nw in &mut self.inputs[id.0] {
+                *nw = NodeWeight::uninitialized(nw.node);
+            }
+            self.pending_weights = true;
+        }
+
+        self.order = None;
+        self.nodes[id.0].replace(new_node)
+    }
+
+    /// Whether a is the logit of b, i.e. b is a `SoftmaxNode` which reads its own logit.
+    fn is_logit_of(&self, a: NodeId, b: NodeId) -> bool {
+        match self.node(b).kind() {
+            NodeKind::Softmax { logit, .. } => logit == a,
+            _ => false,
+        }
+    }
+
+    /// The input connections of a node, in the order they were connected.
+    pub fn input_weights(&self, id: NodeId) -> &[NodeWeight] {
+        &self.inputs[id.0]
+    }
+
+    pub fn input_weights_mut(&mut self, id: NodeId) -> &mut [NodeWeight] {
+        // `NodeWeight.initialized` may be cleared through this
+        self.pending_weights = true;
+        &mut self.inputs[id.0]
+    }
+
+    /// Get a list of nodes connected as inputs of a node.
+    pub fn input_nodes(&self, id: NodeId) -> Vec<NodeId> {
+        self.inputs[id.0].iter().map(|nw| nw.node).collect()
+    }
+
+    /// Get a list of nodes receiving a node's output as a parameter.
+    pub fn output_nodes(&self, id: NodeId) -> &[NodeId] {
+        &self.outputs[id.0]
+    }
+
+    /// The `NodeInput`s passed to the methods of the node `id`, based on the last calculated
+    /// activations of its input nodes.
+    pub fn node_inputs(&self, id: NodeId) -> Vec<NodeInput> {
+        self.inputs[id.0]
+            .iter()
+            .map(|nw| NodeInput {
+                node: nw.node,
+                activation: self.node(nw.node).get_last_calc_activation(),
+                weight: nw.weight,
+            })
+            .collect()
+    }
+
+    /// Orders all nodes such that every node comes after all of its input nodes, breaking
+    /// ties by order of creation.
+    ///
+    /// The order is only calculated again after nodes or connections have been added since
+    /// the last call.
+    ///
+    /// Panics if the graph contains a cycle.
+    pub fn topological_order(&mut self) -> &[NodeId] {
+        if self.order.is_none() {
+            self.order = Some(self.calc_topological_order());
+        }
+
+        self.order.as_ref().unwrap()
+    }
+
+    fn calc_topological_order(&self) -> Vec<NodeId> {
+        // Kahn's algorithm
+        let mut remaining_inputs: Vec<usize> = self.inputs.iter().map(|x| x.len()).collect();
+        let mut queue: VecDeque<NodeId> = self
+            .node_ids()
+            .into_iter()
+            .filter(|id| remaining_inputs[id.0] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(self.len());
+
+        while let Some(id) = queue.pop_front() {
+            order.push(id);
+
+            for &output in &self.outputs[id.0] {
+                remaining_inputs[output.0] -= 1;
+                if remaining_inputs[output.0] == 0 {
+                    queue.push_back(output);
+                }
+            }
+        }
+
+        assert_eq!(
+            order.len(),
+            self.len(),
+            "Graph contains a cycle, cannot determine the order to calculate the nodes in! \
+             See `Network.validate()`."
+        );
+
+        order
+    }
+
+    /// Groups of nodes that lie on a cycle, i.e. the strongly connected components
+    /// consisting of more than one node, or of a single node connected to itself.
+    ///
+    /// Each group is ordered by `NodeId`, and the groups are ordered by their first node.
+    pub fn cycles(&self) -> Vec<Vec<NodeId>> {
+        // Kosaraju's algorithm, with iterative depth-first searches so that
+        // deep graphs don't overflow the stack.
+
+        // 1. Order the nodes by when their depth-first search over the outputs finished
+        let mut visited = vec![false; self.nodes.len()];
+        let mut finished = Vec::with_capacity(self.len());
+
+        for root in self.node_ids() {
+            if visited[root.0] {
+                continue;
+            }
+            visited[root.0] = true;
+
+            // (node, index of the next output to visit)
+            let mut stack = vec![(root, 0)];
+            while let Some(&mut (id, ref mut next)) = stack.last_mut() {
+                if let Some(&output) = self.outputs[id.0].get(*next) {
+                    *next += 1;
+                    if !visited[output.0] {
+                        visited[output.0] = true;
+                        stack.push((output, 0));
+                    }
+                } else {
+                    finished.push(id);
+                    stack.pop();
+                }
+            }
+        }
+
+        // 2. In reverse finishing order, every search over the inputs that doesn't enter
+        //    an earlier component finds exactly one strongly connected component.
+        let mut assigned = vec![false; self.nodes.len()];
+        let mut cycles = vec![];
+
+        for &root in finished.iter().rev() {
+            if assigned[root.0] {
+                continue;
+            }
+            assigned[root.0] = true;
+
+            let mut component = vec![];
+            let mut stack = vec![root];
+            while let Some(id) = stack.pop() {
+                component.push(id);
+                for nw in &self.inputs[id.0] {
+                    if !assigned[nw.node.0] {
+                        assigned[nw.node.0] = true;
+                        stack.push(nw.node);
+                    }
+                }
+            }
+
+            if component.len() > 1 || self.outputs[root.0].contains(&root) {
+                component.sort();
+                cycles.push(component);
+            }
+        }
+
+        cycles.sort();
+        cycles
+    }
+
+    /// Whether each node (indexed by `NodeId`) can be reached by following connections
+    /// from any of `sources`. The sources themselves are reachable.
+    pub fn reachable_from(&self, sources: &[NodeId]) -> Vec<bool> {
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut queue: VecDeque<NodeId> = VecDeque::new();
+
+        for &id in sources {
+            if !reachable[id.0] {
+                reachable[id.0] = true;
+                queue.push_back(id);
+            }
+        }
+
+        while let Some(id) = queue.pop_front() {
+            for &output in &self.outputs[id.0] {
+                if !reachable[output.0] {
+                    reachable[output.0] = true;
+                    queue.push_back(output);
+                }
+            }
+        }
+
+        reachable
+    }
+
+    /// Calculates the activation of every node exactly once, in topological order so that
+    /// each node reads the already updated activations of its inputs through
+    /// `get_last_calc_activation()`.
+    pub fn calc_activations(&mut self) {
+        self.topological_order();
+        let order = self.order.take().unwrap();
+
+        for &id in &order {
+            let inputs = self.node_inputs(id);
+            self.node_mut(id).calc_activation(&inputs);
+        }
+
+        self.order = Some(order);
+    }
+
+    /// Calculates the derivative of the loss function against the activation value (as per
+    /// `get_last_calc_activation()`) of every node reachable from `sources`, i.e. the input
+    /// nodes, then stores it in the node's `TrainingState.dloss` along with the iteration of
+    /// `calc_state`, for later use when updating the weights.
+    ///
+    /// Note that the activations must be calculated first with `calc_activations()`.
+    ///
+    /// The nodes are visited once each in reverse topological order, so that all output
+    /// nodes of a node are done before it. Each node then adds its share of the derivative
+    /// into its input nodes:
+    /// d(loss) / d(input activation) += d(activation) / d(input activation) * d(loss) / d(activation)
+    pub fn calc_activation_derivatives(
+        &mut self,
+        sources: &[NodeId],
+        calc_state: &DerivativeCalculationParams,
+    ) {
+        let iteration = calc_state.calc_derivative_iteration();
+        let reachable = self.reachable_from(sources);
+
+        self.topological_order();
+        let order = self.order.take().unwrap();
+
+        // d(loss) / d(activation) summed over the output connections of each node so far
+        let mut dloss_sums = vec![0.0; self.nodes.len()];
+
+        for &id in order.iter().rev() {
+            if !reachable[id.0] {
+                continue;
+            }
+
+            // Nodes without output nodes that aren't output nodes either have no gradient,
+            // see `ValidationReport.dangling_outputs`
+            let dloss = if !self.outputs[id.0].is_empty() {
+                dloss_sums[id.0]
+            } else {
+                calc_state.get_loss_fn_derivative(id).unwrap_or(0.0)
+            };
+
+            let inputs = self.node_inputs(id);
+            let node = self.nodes[id.0].as_mut().unwrap();
+
+            for (idx, input) in inputs.iter().enumerate() {
+                if reachable[input.node.0] {
+                    dloss_sums[input.node.0] += node.calc_derivative_against(&inputs, idx) * dloss;
+                }
+            }
+
+            let training_state = node.get_training_state_mut();
+            training_state.calc_derivative_iteration = iteration;
+            training_state.dloss = dloss;
+        }
+
+        self.order = Some(order);
+    }
+
+    /// Adds d(loss) / d(weight) of every weighted connection to `NodeWeight.gradient`,
+    /// based on the previously calculated dloss of the receiving node and the activations.
+    ///
+    /// Note that `calc_activation_derivatives()` must be called first with `iteration`.
+    /// Nodes it didn't reach in that iteration are skipped, as their dloss is left over from
+    /// an earlier one. Each weight's d(loss)/d(weight) is also kept in
+    /// `NodeWeight.last_gradient`.
+    pub fn accumulate_gradients(&mut self, iteration: i32) {
+        for id in self.node_ids() {
+            let node = self.nodes[id.0].as_ref().unwrap();
+            if !node.is_weighted() || self.inputs[id.0].is_empty() {
+                continue;
+            }
+
+            if node.get_training_state().calc_derivative_iteration != iteration {
+                for nw in &mut self.inputs[id.0] {
+                    nw.last_gradient = 0.0;
+                }
+                continue;
+            }
+
+            let dloss_dactv = node.get_training_state().dloss;
+            let inputs = self.node_inputs(id);
+
+            for (idx, nw) in self.inputs[id.0].iter_mut().enumerate() {
+                nw.last_gradient = dloss_dactv * node.calc_derivative_against_weight(&inputs, idx);
+                nw.gradient += nw.last_gradient;
+            }
+        }
+    }
+
+    /// Updates the weights of all weighted connections based on the gradients accumulated by
+    /// `accumulate_gradients()` since the last update, then resets the accumulated gradients.
+    pub fn update_weights(&mut self, params: &WeightUpdateParams) {
+        for (node, inputs) in self.nodes.iter().zip(self.inputs.iter_mut()) {
+            if let Some(node) = node {
+                if node.is_weighted() {
+                    update_input_weights(inputs, params);
+                }
+            }
+        }
+    }
+
+    /// Calls `f(receiving node, input node, weight)` on every weighted connection.
+    pub fn visit_weights<F>(&self, mut f: F)
+    where
+        F: FnMut(&(dyn Node + Send), &(dyn Node + Send), &NodeWeight),
+    {
+        for (node, inputs) in self.nodes.iter().zip(self.inputs.iter()) {
+            if let Some(node) = node {
+                if node.is_weighted() {
+                    for nw in inputs {
+                        f(&**node, self.node(nw.node), nw);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls `f(receiving node, input node, weight)` on every weighted connection, allowing
+    /// the weight to be modified.
+    pub fn visit_weights_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&(dyn Node + Send), &(dyn Node + Send), &mut NodeWeight),
+    {
+        let nodes = &self.nodes;
+
+        for (node, inputs) in nodes.iter().zip(self.inputs.iter_mut()) {
+            if let Some(node) = node {
+                if node.is_weighted() {
+                    for nw in inputs.iter_mut() {
+                        // connections never involve removed nodes
+                        f(&**node, &**nodes[nw.node.0].as_ref().unwrap(), nw);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether there may be connections made with `connect()` whose weights are yet to be drawn
+    /// by `initialize_weights()`.
+    pub fn has_pending_weights(&self) -> bool {
+        self.pending_weights
+    }
+
+    /// Draw the weights of all connections made with `connect()` that are yet to be
+    /// initialized, using each receiving node's number of inputs as the fan-in, and each
+    /// sending node's number of outputs as the fan-out.
+    ///
+    /// Weights set with `connect_init()`, or that were initialized previously, are untouched.
+    pub fn initialize_weights<R: Rng + ?Sized>(&mut self, initializer: &Initializer, rng: &mut R) {
+        self.pending_weights = false;
+        let outputs = &self.outputs;
+
+        for weights in &mut self.inputs {
+            let fan_in = weights.len();
+
+            for nw in weights.iter_mut().filter(|nw| !nw.initialized) {
+                let fan_out = outputs[nw.node.0].len();
+
+                nw.weight = initializer.sample(fan_in, fan_out, rng);
+                nw.initialized = true;
+            }
+        }
+    }
+}
+
+impl Default for Graph {
+    fn default() -> Graph {
+        Graph::new()
+    }
+}
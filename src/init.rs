@@ -0,0 +1,69 @@
+//!
+//! Weight initialisation strategies
+//!
+
+use rand::distributions::Normal;
+use rand::Rng;
+
+/// Strategy used to draw the initial weight of a connection between two nodes.
+///
+/// `fan_in` refers to the number of inputs of the node receiving the connection, and
+/// `fan_out` refers to the number of outputs of the node the connection originates from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Initializer {
+    /// Xavier/Glorot uniform: U(-sqrt(6 / (fan_in + fan_out)), sqrt(6 / (fan_in + fan_out)))
+    XavierUniform,
+    /// Xavier/Glorot normal: N(0, sqrt(2 / (fan_in + fan_out)))
+    XavierNormal,
+    /// He uniform: U(-sqrt(6 / fan_in), sqrt(6 / fan_in)). Suited for ReLU-like activations.
+    HeUniform,
+    /// He normal: N(0, sqrt(2 / fan_in)). Suited for ReLU-like activations.
+    HeNormal,
+    /// LeCun uniform: U(-sqrt(3 / fan_in), sqrt(3 / fan_in))
+    LecunUniform,
+    /// LeCun normal: N(0, sqrt(1 / fan_in))
+    LecunNormal,
+    /// Every weight is set to the given value
+    Constant(f64),
+    /// U(low, high)
+    Uniform(f64, f64),
+    /// N(mean, standard deviation)
+    Normal(f64, f64),
+}
+
+impl Initializer {
+    /// Draw one weight for a connection with the given `fan_in` and `fan_out`.
+    pub fn sample<R: Rng + ?Sized>(&self, fan_in: usize, fan_out: usize, rng: &mut R) -> f64 {
+        // Avoid dividing by zero for nodes that have yet to be fully connected
+        let fan_in = fan_in.max(1) as f64;
+        let fan_out = fan_out.max(1) as f64;
+
+        match *self {
+            Initializer::XavierUniform => uniform(rng, (6.0 / (fan_in + fan_out)).sqrt()),
+            Initializer::XavierNormal => normal(rng, 0.0, (2.0 / (fan_in + fan_out)).sqrt()),
+            Initializer::HeUniform => uniform(rng, (6.0 / fan_in).sqrt()),
+            Initializer::HeNormal => normal(rng, 0.0, (2.0 / fan_in).sqrt()),
+            Initializer::LecunUniform => uniform(rng, (3.0 / fan_in).sqrt()),
+            Initializer::LecunNormal => normal(rng, 0.0, (1.0 / fan_in).sqrt()),
+            Initializer::Constant(value) => value,
+            Initializer::Uniform(low, high) => rng.gen_range(low, high),
+            Initializer::Normal(mean, std_dev) => normal(rng, mean, std_dev),
+        }
+    }
+}
+
+impl Default for Initializer {
+    /// U(-1, 1)
+    fn default() -> Initializer {
+        Initializer::Uniform(-1.0, 1.0)
+    }
+}
+
+/// U(-limit, limit)
+fn uniform<R: Rng + ?Sized>(rng: &mut R, limit: f64) -> f64 {
+    rng.gen_range(-limit, limit)
+}
+
+fn normal<R: Rng + ?Sized>(rng: &mut R, mean: f64, std_dev: f64) -> f64 {
+    rng.sample(Normal::new(mean, std_dev))
+}
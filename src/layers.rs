@@ -1,13 +1,31 @@
+use graph::{Graph, NodeId};
+use loss::Loss;
 use ndarray::prelude::Array2;
-use ndarray::ArrayBase;
 use ndarray::Axis;
-use node::InputNode;
-use node::Node;
-use std::collections::HashMap;
-use AM;
+
+/// Reshapes a flattened array of values into a 2D array where each row contains
+/// `node_count` values, one value for each node, according to the same index.
+pub fn to_rows(vals: &[f64], node_count: usize) -> Array2<f64> {
+    assert!(
+        vals.len() % node_count == 0,
+        "vals.len() must be a multiple of the number of nodes!"
+    );
+
+    let mut rows = Array2::<f64>::zeros((vals.len() / node_count, node_count));
+
+    for (idx, val) in vals.iter().enumerate() {
+        let row = idx / node_count;
+        let column = idx % node_count;
+
+        rows[[row, column]] = *val;
+    }
+
+    rows
+}
 
 pub struct InputLayer {
-    pub input_nodes: Vec<AM<InputNode>>,
+    /// `InputNode`s, in the same index order as the columns of `training_inputs`
+    pub input_nodes: Vec<NodeId>,
     pub training_inputs: Array2<f64>,
 }
 
@@ -15,28 +33,10 @@ impl InputLayer {
     /// `training_vals` is a flattened array of training input values
     /// `training_vals[0 .. nodes.len()]` represents one single input value
     /// where each of the values corresponds to one input node, according to the same index.
-    pub fn new(nodes: &Vec<AM<InputNode>>, training_vals: &[f64]) -> InputLayer {
-        let mut input_nodes = vec![];
-        for node in nodes {
-            input_nodes.push(node.clone())
-        }
-
-        let node_count = input_nodes.len();
+    pub fn new(nodes: &[NodeId], training_vals: &[f64]) -> InputLayer {
+        let input_nodes = nodes.to_vec();
 
-        assert!(
-            training_vals.len() % node_count == 0,
-            "training_vals.len() must be a multiple of nodes.len()!"
-        );
-
-        let mut training_inputs =
-            Array2::<f64>::zeros((training_vals.len() / node_count, node_count));
-
-        for (idx, val) in training_vals.iter().enumerate() {
-            let row = idx / node_count;
-            let column = idx % node_count;
-
-            training_inputs[[row, column]] = *val
-        }
+        let training_inputs = to_rows(training_vals, input_nodes.len());
 
         InputLayer {
             input_nodes,
@@ -49,27 +49,35 @@ impl InputLayer {
     /// The `iter` parameter is a ring which wraps around 0 and `self.training_inputs.len()`
     /// E.g. assuming iter is 5, and `training_inputs` has 3 vectors of node input values,
     /// the nodes will be assigned to the values given by index 2 (5 % 3) of `training_inputs`.
-    pub fn set_iteration(&mut self, iter: usize) {
-        println!("Setting input iteration: {}", iter);
+    pub fn set_iteration(&self, graph: &mut Graph, iter: usize) {
+        let idx = iter % self.training_inputs.len_of(Axis(0));
 
-        let idx = iter % self.training_inputs.len();
+        let vals = self.training_inputs.slice(s![idx, ..]).to_vec();
+        self.set_input_values(graph, &vals);
+    }
 
-        let vals = self.training_inputs.slice(s![idx, ..]);
-        for (idx, val) in vals.iter().enumerate() {
-            let mut node = self.input_nodes[idx].lock().unwrap();
+    /// Assigns the input_nodes input values directly, e.g. to feed the network with a
+    /// sample that isn't part of the training dataset.
+    ///
+    /// `vals[i]` is assigned to `self.input_nodes[i]`.
+    pub fn set_input_values(&self, graph: &mut Graph, vals: &[f64]) {
+        assert_eq!(
+            vals.len(),
+            self.input_nodes.len(),
+            "Expected one input value per input node!"
+        );
 
-            println!("Assigning input [{}] to {}", node.name(), val);
-            node.value = *val;
+        for (&node, &val) in self.input_nodes.iter().zip(vals.iter()) {
+            graph.node_mut(node).set_value(val);
         }
     }
 }
 
 pub struct OutputLayer {
-    pub output_nodes: Vec<AM<dyn Node>>,
-    pub node_name_to_index_map: HashMap<String, usize>,
+    pub output_nodes: Vec<NodeId>,
     pub training_ground_truths: Array2<f64>,
-    /// Fn(List of current activation values, list of corresponding ground truth values) -> Loss score
-    pub loss_function: Box<dyn Fn(Vec<f64>, Vec<f64>) -> f64>,
+    /// Gives both the loss and d(loss) / d(activation) of each output node
+    pub loss: Box<dyn Loss>,
 }
 
 impl OutputLayer {
@@ -77,100 +85,78 @@ impl OutputLayer {
     /// `_training_ground_truths[0 .. nodes.len()]` represents one single ground truth value
     /// where each of the values corresponds to the output nodes, according to the same index.
     ///
-    /// `loss_function` is a function that accepts two params:
-    /// `| output_node_activations, expected_ground_truths |`.
-    /// `output_node_activations` is a `Vec<f64>` that lists the activation values of the output nodes
-    /// in the same index order as `self.output_nodes`.
-    /// `expected_ground_truths` represents the correct activation values of each output node
-    /// for any particular iteration in the same index order as `self.output_nodes`.
-    /// The `loss_function` should calculate and return the loss score based on the two parameters provided.
-    ///
+    /// `loss` scores the activation values of the output nodes against the expected ground truths,
+    /// both in the same index order as `self.output_nodes`, and gives the partial derivative
+    /// of the loss against each output node's activation, e.g. `Box::new(MeanSquaredError)`.
     pub fn new(
-        nodes: &Vec<AM<dyn Node>>,
+        nodes: &[NodeId],
         _training_ground_truths: &[f64],
-        loss_function: Box<dyn Fn(Vec<f64>, Vec<f64>) -> f64>,
+        loss: Box<dyn Loss>,
     ) -> OutputLayer {
-        let mut output_nodes = vec![];
-        let mut node_name_to_index_map = HashMap::new();
-
-        for (idx, node) in nodes.iter().enumerate() {
-            output_nodes.push(node.clone());
-            let node = node.lock().unwrap();
-            node_name_to_index_map.insert(node.name().to_string(), idx);
-        }
+        let output_nodes = nodes.to_vec();
 
-        let node_count = output_nodes.len();
-
-        assert!(
-            _training_ground_truths.len() % node_count == 0,
-            "training_vals.len() must be a multiple of nodes.len()!"
-        );
-
-        let mut training_ground_truths =
-            Array2::<f64>::zeros((_training_ground_truths.len() / node_count, node_count));
-
-        for (idx, val) in _training_ground_truths.iter().enumerate() {
-            let row = idx / node_count;
-            let column = idx % node_count;
-
-            training_ground_truths[[row, column]] = *val;
-        }
+        let training_ground_truths = to_rows(_training_ground_truths, output_nodes.len());
 
         OutputLayer {
             output_nodes,
-            node_name_to_index_map,
             training_ground_truths,
-            loss_function,
+            loss,
         }
     }
 
     /// Calculates the loss of one particular iteration
-    /// Make sure `InputLayer.set_iteration(iter)` is called with the same `iter` value first!
+    /// Make sure `InputLayer.set_iteration(iter)` is called with the same `iter` value,
+    /// and the activations calculated with `Graph.calc_activations()` first!
     ///
     /// The `iter` parameter is a ring which wraps around 0 and `self.training_inputs.len()`
     /// E.g. assuming iter is 5, and `training_inputs` has 3 vectors of node input values,
     /// the nodes will be assigned to the values given by index 2 (5 % 3) of `training_inputs`.
-    pub fn calculate_iter_loss(&self, iter: usize) -> f64 {
-        let idx = iter % self.training_ground_truths.len();
-
-        let vals = self.training_ground_truths.slice(s![idx, ..]);
-        let mut output_node_activations = vec![];
-
-        for (idx, node_ground_truth_val) in vals.iter().enumerate() {
-            let mut node = self.output_nodes[idx].lock().unwrap();
-
-            let activation = node.calc_activation();
-            output_node_activations.push(activation);
-            println!(
-                "activation: {}, ground: {}",
-                activation, node_ground_truth_val
-            );
-        }
+    pub fn calculate_iter_loss(&self, graph: &Graph, iter: usize) -> f64 {
+        self.calculate_loss(graph, &self.get_iter_ground_truths(iter))
+    }
 
-        let mut expected_ground_truths: Vec<f64> = vals.to_vec();
+    /// Calculates the loss of the current output node activations against `ground_truths`,
+    /// where `ground_truths[i]` is the expected activation of `self.output_nodes[i]`.
+    ///
+    /// Make sure the input nodes are assigned the corresponding input values, and the
+    /// activations calculated with `Graph.calc_activations()` first!
+    pub fn calculate_loss(&self, graph: &Graph, ground_truths: &[f64]) -> f64 {
+        let output_node_activations = self.get_last_activations(graph);
 
         assert_eq!(
             output_node_activations.len(),
-            expected_ground_truths.len(),
-            "Unexpected error!!??"
+            ground_truths.len(),
+            "Expected one ground truth value per output node!"
         );
 
-        (self.loss_function)(output_node_activations, expected_ground_truths)
+        self.loss.loss(&output_node_activations, ground_truths)
     }
 
     /// Get a single training ground truth value for one node at a particular iteration
-    pub fn get_ground_truth(&self, iter: usize, node_name: &str) -> f64 {
-        self.training_ground_truths[[iter, *self.node_name_to_index_map.get(node_name).unwrap()]]
+    pub fn get_ground_truth(&self, iter: usize, node: NodeId) -> f64 {
+        let column = self
+            .output_nodes
+            .iter()
+            .position(|&x| x == node)
+            .expect("Not an output node!");
+
+        self.training_ground_truths[[iter, column]]
     }
 
-    /// Get a map of (node name, ground truth) KVPs for a particular iteration
-    pub fn get_ground_truths(&self, iter: usize) -> HashMap<String, f64> {
-        let mut map = HashMap::new();
-        let a = self.training_ground_truths.slice(s![iter, ..]);
-        for (idx, node) in self.output_nodes.iter().enumerate() {
-            map.insert(node.lock().unwrap().name().to_string(), a[idx]);
-        }
+    /// Get the ground truth values of a particular iteration, in the same index order as
+    /// `self.output_nodes`. `iter` wraps around like in `calculate_iter_loss()`.
+    pub fn get_iter_ground_truths(&self, iter: usize) -> Vec<f64> {
+        let idx = iter % self.training_ground_truths.len_of(Axis(0));
+
+        self.training_ground_truths.slice(s![idx, ..]).to_vec()
+    }
 
-        map
+    /// Get the last calculated activations of the output nodes, in the same index order as
+    /// `self.output_nodes`.
+    pub fn get_last_activations(&self, graph: &Graph) -> Vec<f64> {
+        self.output_nodes
+            .iter()
+            .map(|&node| graph.node(node).get_last_calc_activation())
+            .collect()
     }
 }
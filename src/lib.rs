@@ -0,0 +1,28 @@
+// Node constructors add the node into a Graph and return its NodeId
+#![allow(clippy::new_ret_no_self)]
+
+pub mod activation;
+pub mod builder;
+pub mod checkpoint;
+pub mod dot;
+pub mod graph;
+pub mod init;
+pub mod layers;
+pub mod loss;
+pub mod model;
+pub mod network;
+pub mod node;
+pub mod optimizer;
+pub mod regularization;
+pub mod schedule;
+pub mod softmax;
+#[cfg(test)]
+mod test_utils;
+
+extern crate rand;
+#[macro_use(s)]
+extern crate ndarray;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
@@ -0,0 +1,232 @@
+//!
+//! Loss functions which score the output node activations against the ground truths
+//!
+
+use regularization::sign;
+use std::f64;
+
+/// Scores the activations of the output nodes against the ground truths of one sample.
+///
+/// `activations[i]` and `ground_truths[i]` belong to `OutputLayer.output_nodes[i]`.
+pub trait Loss {
+    /// Returns the loss, along with d(loss) / d(activation) of each output node
+    /// in the same index order as `activations`.
+    fn loss_and_gradient(&self, activations: &[f64], ground_truths: &[f64]) -> (f64, Vec<f64>);
+
+    /// Returns only the loss, for when the gradient is not needed.
+    fn loss(&self, activations: &[f64], ground_truths: &[f64]) -> f64 {
+        self.loss_and_gradient(activations, ground_truths).0
+    }
+}
+
+/// Mean of (activation - ground truth)^2 over the output nodes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MeanSquaredError;
+
+impl Loss for MeanSquaredError {
+    fn loss_and_gradient(&self, activations: &[f64], ground_truths: &[f64]) -> (f64, Vec<f64>) {
+        mean_of(activations, ground_truths, |a, t| {
+            ((a - t).powi(2), 2.0 * (a - t))
+        })
+    }
+}
+
+/// Mean of |activation - ground truth| over the output nodes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MeanAbsoluteError;
+
+impl Loss for MeanAbsoluteError {
+    fn loss_and_gradient(&self, activations: &[f64], ground_truths: &[f64]) -> (f64, Vec<f64>) {
+        mean_of(activations, ground_truths, |a, t| {
+            ((a - t).abs(), sign(a - t))
+        })
+    }
+}
+
+/// Squared error for errors within `delta`, and absolute error beyond it, which makes it
+/// less sensitive to outliers than `MeanSquaredError`. Averaged over the output nodes.
+#[derive(Debug, Clone, Copy)]
+pub struct Huber {
+    /// Default: 1.0
+    pub delta: f64,
+}
+
+impl Default for Huber {
+    fn default() -> Huber {
+        Huber { delta: 1.0 }
+    }
+}
+
+impl Loss for Huber {
+    fn loss_and_gradient(&self, activations: &[f64], ground_truths: &[f64]) -> (f64, Vec<f64>) {
+        let delta = self.delta;
+
+        mean_of(activations, ground_truths, |a, t| {
+            let error = a - t;
+            if error.abs() <= delta {
+                (0.5 * error * error, error)
+            } else {
+                (delta * (error.abs() - 0.5 * delta), delta * sign(error))
+            }
+        })
+    }
+}
+
+/// Mean of ln(cosh(activation - ground truth)) over the output nodes.
+/// Behaves like squared error for small errors and like absolute error for large errors.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogCosh;
+
+impl Loss for LogCosh {
+    fn loss_and_gradient(&self, activations: &[f64], ground_truths: &[f64]) -> (f64, Vec<f64>) {
+        mean_of(activations, ground_truths, |a, t| {
+            let error = a - t;
+            /*
+            ln(cosh(x)) = ln((e^x + e^-x) / 2)
+                        = |x| + ln(1 + e^(-2|x|)) - ln(2)
+            which doesn't overflow for large |x|.
+            */
+            let loss = error.abs() + (-2.0 * error.abs()).exp().ln_1p() - f64::consts::LN_2;
+            (loss, error.tanh())
+        })
+    }
+}
+
+/// Mean of -(y ln(p) + (1 - y) ln(1 - p)) over the output nodes, where each activation p
+/// is a probability in (0, 1), e.g. from a sigmoid, and each ground truth y is 0 or 1.
+#[derive(Debug, Clone, Copy)]
+pub struct BinaryCrossEntropy {
+    /// Activations are clamped to `[epsilon, 1 - epsilon]` to avoid ln(0), where the
+    /// gradient is 0. Default: 1e-7
+    pub epsilon: f64,
+}
+
+impl Default for BinaryCrossEntropy {
+    fn default() -> BinaryCrossEntropy {
+        BinaryCrossEntropy { epsilon: 1e-7 }
+    }
+}
+
+impl Loss for BinaryCrossEntropy {
+    fn loss_and_gradient(&self, activations: &[f64], ground_truths: &[f64]) -> (f64, Vec<f64>) {
+        let epsilon = self.epsilon;
+
+        mean_of(activations, ground_truths, |a, t| {
+            let p = a.max(epsilon).min(1.0 - epsilon);
+            let loss = -(t * p.ln() + (1.0 - t) * (1.0 - p).ln());
+            // The loss is flat where the activation is clamped
+            let gradient = if p != a {
+                0.0
+            } else {
+                (p - t) / (p * (1.0 - p))
+            };
+            (loss, gradient)
+        })
+    }
+}
+
+/// -sum(y ln(p)) over the output nodes, where the activations p are a probability
+/// distribution, e.g. from a softmax, and the ground truths y are one-hot encoded.
+///
+/// Unlike the other losses this is a sum rather than a mean: the output nodes together make
+/// up one distribution, and dividing by their number would scale the loss and gradient with
+/// the number of classes.
+#[derive(Debug, Clone, Copy)]
+pub struct CategoricalCrossEntropy {
+    /// Activations are clamped to at least `epsilon` to avoid ln(0), where the gradient is 0.
+    /// Default: 1e-7
+    pub epsilon: f64,
+}
+
+impl Default for CategoricalCrossEntropy {
+    fn default() -> CategoricalCrossEntropy {
+        CategoricalCrossEntropy { epsilon: 1e-7 }
+    }
+}
+
+impl Loss for CategoricalCrossEntropy {
+    fn loss_and_gradient(&self, activations: &[f64], ground_truths: &[f64]) -> (f64, Vec<f64>) {
+        let mut loss = 0.0;
+        let mut gradient = Vec::with_capacity(activations.len());
+
+        for (&a, &t) in activations.iter().zip(ground_truths.iter()) {
+            let p = a.max(self.epsilon);
+            loss -= t * p.ln();
+            // The loss is flat where the activation is clamped
+            gradient.push(if p != a { 0.0 } else { -t / p });
+        }
+
+        (loss, gradient)
+    }
+}
+
+/// Categorical cross-entropy whose gradient is d(loss) / d(logits) = p - y instead of
+/// d(loss) / d(p), for use with `SoftmaxGroup::fused_with_cross_entropy()` only.
+///
+/// The loss is the same as `CategoricalCrossEntropy`.
+#[derive(Debug, Clone, Copy)]
+pub struct SoftmaxCrossEntropy {
+    /// Activations are clamped to at least `epsilon` to avoid ln(0). Default: 1e-7
+    pub epsilon: f64,
+}
+
+impl Default for SoftmaxCrossEntropy {
+    fn default() -> SoftmaxCrossEntropy {
+        SoftmaxCrossEntropy { epsilon: 1e-7 }
+    }
+}
+
+impl Loss for SoftmaxCrossEntropy {
+    fn loss_and_gradient(&self, activations: &[f64], ground_truths: &[f64]) -> (f64, Vec<f64>) {
+        let loss = CategoricalCrossEntropy {
+            epsilon: self.epsilon,
+        }
+        .loss(activations, ground_truths);
+
+        let gradient = activations
+            .iter()
+            .zip(ground_truths.iter())
+            .map(|(p, y)| p - y)
+            .collect();
+
+        (loss, gradient)
+    }
+}
+
+/// Mean of max(0, 1 - y * activation) over the output nodes, where each ground truth y
+/// is -1 or 1.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hinge;
+
+impl Loss for Hinge {
+    fn loss_and_gradient(&self, activations: &[f64], ground_truths: &[f64]) -> (f64, Vec<f64>) {
+        mean_of(activations, ground_truths, |a, t| {
+            let margin = 1.0 - t * a;
+            if margin > 0.0 {
+                (margin, -t)
+            } else {
+                (0.0, 0.0)
+            }
+        })
+    }
+}
+
+/// Averages a separable loss over the output nodes.
+///
+/// `f`: Fn(activation, ground truth) -> (loss of the node, d(loss of the node) / d(activation))
+fn mean_of<F>(activations: &[f64], ground_truths: &[f64], f: F) -> (f64, Vec<f64>)
+where
+    F: Fn(f64, f64) -> (f64, f64),
+{
+    let n = activations.len() as f64;
+    let mut loss = 0.0;
+    let mut gradient = Vec::with_capacity(activations.len());
+
+    for (&a, &t) in activations.iter().zip(ground_truths.iter()) {
+        let (node_loss, node_gradient) = f(a, t);
+        loss += node_loss / n;
+        gradient.push(node_gradient / n);
+    }
+
+    (loss, gradient)
+}
@@ -1,34 +1,27 @@
-mod layers;
-mod network;
-mod node;
-
-#[macro_use]
-extern crate lazy_static;
+extern crate neural_network;
 extern crate rand;
-#[macro_use(s)]
-extern crate ndarray;
 
 use rand::Rng;
-use std::sync::{Arc, Mutex};
-
-use layers::{InputLayer, OutputLayer};
-use network::Network;
-use node::*;
 
-/// Arc Mutex helpers because garbage collection
-pub type AM<T> = Arc<Mutex<T>>;
-
-pub fn am<T>(x: T) -> Arc<Mutex<T>> {
-    Arc::new(Mutex::new(x))
-}
+use neural_network::graph::Graph;
+use neural_network::layers::{InputLayer, OutputLayer};
+use neural_network::loss::MeanSquaredError;
+use neural_network::network::{Network, NetworkConfigs};
+use neural_network::node::*;
 
 fn main() {
     println!("rusty-brain v0.1: a + 2b test");
 
+    let network_configs = NetworkConfigs {
+        seed: 42,
+        shuffle: true,
+        ..Default::default()
+    };
+
     let mut training_vals = vec![];
     let mut ground_truths = vec![];
 
-    let mut rng = rand::thread_rng();
+    let mut rng = network_configs.seeded_rng();
     for _ in 0..1000 {
         let a = rng.gen_range(0f64, 5f64);
         let b = rng.gen_range(0f64, 5f64);
@@ -38,52 +31,51 @@ fn main() {
         ground_truths.push(a + 2.0 * b);
     }
 
-    // Make inputs
-    let mut i1 = InputNode::new("i1", 0.6);
-    let mut i2 = InputNode::new("i2", 1.0);
+    let mut validation_vals = vec![];
+    let mut validation_ground_truths = vec![];
 
-    let mut input_layer = InputLayer::new(&vec![i1.clone(), i2.clone()], &training_vals);
+    for _ in 0..200 {
+        let a = rng.gen_range(0f64, 5f64);
+        let b = rng.gen_range(0f64, 5f64);
+        validation_vals.push(a);
+        validation_vals.push(b);
 
-    let mut s1 = SumNode::new("s1");
+        validation_ground_truths.push(a + 2.0 * b);
+    }
+
+    let mut graph = Graph::new();
 
-    connect_init(i1.clone(), s1.clone(), 1.0);
-    connect_init(i2.clone(), s1.clone(), 0.2);
+    // Make inputs
+    let i1 = InputNode::new(&mut graph, "i1", 0.6);
+    let i2 = InputNode::new(&mut graph, "i2", 1.0);
 
-    let mut output_layer = OutputLayer::new(
-        &vec![s1],
-        &ground_truths,
-        // Loss Function: Root Mean Square Error
-        Box::new(|node_activations: Vec<f64>, ground_truths: Vec<f64>| {
-            let mut mse = 0.0;
+    let input_layer = InputLayer::new(&[i1, i2], &training_vals);
 
-            let actual_expected = node_activations.iter().zip(ground_truths.iter());
+    let s1 = SumNode::new(&mut graph, "s1");
 
-            for (actual, expected) in actual_expected {
-                mse += f64::powi(actual - expected, 2) / (node_activations.len() as f64);
-            }
+    graph.connect_init(i1, s1, 1.0);
+    graph.connect_init(i2, s1, 0.2);
 
-            mse
-        }),
-    );
+    let output_layer = OutputLayer::new(&[s1], &ground_truths, Box::new(MeanSquaredError));
 
-    let mut network = Network::new(input_layer, output_layer);
-    let output_node_count = network.output_layer.output_nodes.len() as f64;
-    for iter in 0..=10 {
-        network.input_layer.set_iteration(iter);
-        let loss = network.output_layer.calculate_iter_loss(iter);
-        println!("Iteration {}: loss = {}", iter, loss);
-        let gt = network.output_layer.get_ground_truths(iter);
-
-        network.evaluate_gradients(iter as i32, move |node_name| {
-            // Lambda to calculate one derivative term of loss of one particular node.
-            // This derivative function is assuming the RMSE function is used.
-            // RMSE = sigma((node, k=10) ==> RMSE(node))
-            // RMSE(node) = (node.activation - node.ground_truth) ^ 2
-            // RMSE'(node) = 2 * (node.activation - node.ground_truth)
-            let nodes = NODES.lock().unwrap();
-            let node = nodes.get(node_name).unwrap();
-            let node = node.lock().unwrap();
-            2.0 * (node.get_last_calc_activation() - gt.get(node_name).unwrap()) / output_node_count
-        })
+    let mut network = Network::with_configs(graph, input_layer, output_layer, network_configs);
+    network.set_validation_data(&validation_vals, &validation_ground_truths);
+
+    for epoch in 0..10 {
+        let summary = network.train_one_epoch();
+        println!(
+            "Epoch {}: mean loss = {}, min loss = {}, max loss = {}, validation loss = {} ({:?})",
+            epoch,
+            summary.mean_loss,
+            summary.min_loss,
+            summary.max_loss,
+            summary.validation_loss.unwrap(),
+            summary.duration
+        );
     }
+
+    println!(
+        "Average training loss: {}",
+        network.calc_avg_training_loss()
+    );
 }
@@ -0,0 +1,326 @@
+//!
+//! The versioned JSON format of saved networks, see `Network::save()` and `Network::load()`
+//!
+
+use activation::Activation;
+use graph::{Graph, NodeId};
+use node::{check_structure, create_nodes, invalid_data, NodeKind};
+use serde_json;
+use std::collections::HashMap;
+use std::io;
+
+/// Stored as `format_version`, `from_json()` only reads models of this version
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A saved network:
+///
+/// ```json
+/// {
+///   "format_version": 1,
+///   "nodes": [
+///     { "kind": "InputNode", "name": "i1", "value": 0.6 },
+///     { "kind": "ConstantNode", "name": "bias", "value": 1.0 },
+///     { "kind": "ActivationNode", "name": "h1", "activation": { "LeakyRelu": 0.01 } },
+///     { "kind": "SumNode", "name": "s1" }
+///   ],
+///   "edges": [
+///     { "from": "i1", "to": "h1", "weight": 0.25 },
+///     { "from": "bias", "to": "h1", "weight": -0.5 },
+///     { "from": "h1", "to": "s1", "weight": 1.5 }
+///   ],
+///   "input_nodes": ["i1"],
+///   "output_nodes": ["s1"]
+/// }
+/// ```
+#[derive(Serialize, Deserialize)]
+struct Model {
+    format_version: u32,
+    /// In order of creation
+    nodes: Vec<NodeRecord>,
+    /// Grouped by receiving node in order of creation, then in the order they were connected
+    edges: Vec<EdgeRecord>,
+    /// Names of the nodes of the `InputLayer`, in order
+    input_nodes: Vec<String>,
+    /// Names of the nodes of the `OutputLayer`, in order
+    output_nodes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind")]
+enum NodeRecord {
+    #[serde(rename = "InputNode")]
+    Input { name: String, value: f64 },
+    #[serde(rename = "ConstantNode")]
+    Constant { name: String, value: f64 },
+    #[serde(rename = "SumNode")]
+    Sum { name: String },
+    #[serde(rename = "SigmoidNode")]
+    Sigmoid { name: String },
+    #[serde(rename = "ActivationNode")]
+    Activation {
+        name: String,
+        activation: Activation,
+    },
+    #[serde(rename = "SoftmaxNode")]
+    Softmax {
+        name: String,
+        /// Name of the node's own logit
+        logit: String,
+        fused_cross_entropy: bool,
+    },
+}
+
+impl NodeRecord {
+    fn name(&self) -> &str {
+        match *self {
+            NodeRecord::Input { ref name, .. }
+            | NodeRecord::Constant { ref name, .. }
+            | NodeRecord::Sum { ref name }
+            | NodeRecord::Sigmoid { ref name }
+            | NodeRecord::Activation { ref name, .. }
+            | NodeRecord::Softmax { ref name, .. } => name,
+        }
+    }
+
+    /// The kind of the node, with the name of the logit of a softmax node
+    fn kind(&self) -> NodeKind<&str> {
+        match *self {
+            NodeRecord::Input { value, .. } => NodeKind::Input(value),
+            NodeRecord::Constant { value, .. } => NodeKind::Constant(value),
+            NodeRecord::Sum { .. } => NodeKind::Sum,
+            NodeRecord::Sigmoid { .. } => NodeKind::Sigmoid,
+            NodeRecord::Activation { activation, .. } => NodeKind::Activation(activation),
+            NodeRecord::Softmax {
+                ref logit,
+                fused_cross_entropy,
+                ..
+            } => NodeKind::Softmax {
+                logit,
+                fused_cross_entropy,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EdgeRecord {
+    from: String,
+    to: String,
+    weight: f64,
+}
+
+/// Writes the nodes and connections of `graph`, and the names of `input_nodes` and
+/// `output_nodes` in order.
+pub fn to_json(graph: &Graph, input_nodes: &[NodeId], output_nodes: &[NodeId]) -> String {
+    let name = |id: NodeId| graph.node(id).name().to_string();
+
+    let mut nodes = vec![];
+    let mut edges = vec![];
+
+    for id in graph.node_ids() {
+        let node_name = name(id);
+
+        nodes.push(match graph.node(id).kind() {
+            NodeKind::Input(value) => NodeRecord::Input {
+                name: node_name.clone(),
+                value,
+            },
+            NodeKind::Constant(value) => NodeRecord::Constant {
+                name: node_name.clone(),
+                value,
+            },
+            NodeKind::Sum => NodeRecord::Sum {
+                name: node_name.clone(),
+            },
+            NodeKind::Sigmoid => NodeRecord::Sigmoid {
+                name: node_name.clone(),
+            },
+            NodeKind::Activation(activation) => NodeRecord::Activation {
+                name: node_name.clone(),
+                activation,
+            },
+            NodeKind::Softmax {
+                logit,
+                fused_cross_entropy,
+            } => NodeRecord::Softmax {
+                name: node_name.clone(),
+                logit: name(logit),
+                fused_cross_entropy,
+            },
+        });
+
+        for nw in graph.input_weights(id) {
+            edges.push(EdgeRecord {
+                from: name(nw.node),
+                to: node_name.clone(),
+                weight: nw.weight,
+            });
+        }
+    }
+
+    let model = Model {
+        format_version: FORMAT_VERSION,
+        nodes,
+        edges,
+        input_nodes: input_nodes.iter().map(|&id| name(id)).collect(),
+        output_nodes: output_nodes.iter().map(|&id| name(id)).collect(),
+    };
+
+    serde_json::to_string_pretty(&model).unwrap()
+}
+
+/// Reads a network written by `to_json()`.
+///
+/// Returns the graph, along with the input and output nodes in order. Errors with
+/// `InvalidData` if the graph has a cycle, an input node isn't an `InputNode`, or a softmax
+/// node isn't connected to its own logit.
+pub fn from_json(json: &str) -> io::Result<(Graph, Vec<NodeId>, Vec<NodeId>)> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+
+    match value.get("format_version").and_then(|v| v.as_u64()) {
+        Some(version) if version == u64::from(FORMAT_VERSION) => {}
+        Some(version) => {
+            return Err(invalid_data(format!(
+                "Unsupported model format version {}, expected {}",
+                version, FORMAT_VERSION
+            )))
+        }
+        None => return Err(invalid_data("Missing model format version".to_string())),
+    }
+
+    let model: Model = serde_json::from_value(value)?;
+
+    let positions: HashMap<&str, usize> = model
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(position, record)| (record.name(), position))
+        .collect();
+
+    let records = model
+        .nodes
+        .iter()
+        .map(|record| {
+            let kind = record.kind().map_logit(|logit| {
+                positions
+                    .get(logit)
+                    .cloned()
+                    .ok_or_else(|| invalid_data(format!("Unknown node [{}]", logit)))
+            })?;
+
+            Ok((record.name().to_string(), kind))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut graph = Graph::new();
+    create_nodes(&mut graph, &records)?;
+
+    for edge in &model.edges {
+        let from = find_node(&graph, &edge.from)?;
+        let to = find_node(&graph, &edge.to)?;
+        graph.connect_init(from, to, edge.weight);
+    }
+
+    let input_nodes = model
+        .input_nodes
+        .iter()
+        .map(|name| find_node(&graph, name))
+        .collect::<io::Result<Vec<_>>>()?;
+    let output_nodes = model
+        .output_nodes
+        .iter()
+        .map(|name| find_node(&graph, name))
+        .collect::<io::Result<_>>()?;
+
+    check_structure(&graph, &input_nodes)?;
+
+    Ok((graph, input_nodes, output_nodes))
+}
+
+fn find_node(graph: &Graph, name: &str) -> io::Result<NodeId> {
+    graph
+        .get_node_id(name)
+        .ok_or_else(|| invalid_data(format!("Unknown node [{}]", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_json, to_json};
+    use network::Network;
+    use serde_json::{self, Value};
+    use std::fs;
+    use std::io;
+    use test_utils::{softmax_network, temp_path};
+
+    #[test]
+    fn loaded_network_has_same_activations() {
+        let mut network = softmax_network();
+
+        let path = temp_path("model-test", "json");
+        network.save(&path).unwrap();
+        let mut loaded = Network::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.graph.len(), network.graph.len());
+        assert!(!loaded.graph.contains_node("removed"));
+
+        for values in &[[0.3, -1.2], [2.5, 0.7]] {
+            for net in &mut [&mut network, &mut loaded] {
+                for (&id, &value) in net.input_layer.input_nodes.iter().zip(values.iter()) {
+                    net.graph.node_mut(id).set_value(value);
+                }
+                net.forward_pass();
+            }
+
+            for id in network.graph.node_ids() {
+                let node = network.graph.node(id);
+                let loaded_node = loaded
+                    .graph
+                    .node(loaded.graph.get_node_id(node.name()).unwrap());
+
+                assert_eq!(
+                    node.get_last_calc_activation().to_bits(),
+                    loaded_node.get_last_calc_activation().to_bits(),
+                    "[{}]",
+                    node.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_structure_is_invalid_data() {
+        let network = softmax_network();
+        let json = to_json(
+            &network.graph,
+            &network.input_layer.input_nodes,
+            &network.output_layer.output_nodes,
+        );
+        let model: Value = serde_json::from_str(&json).unwrap();
+
+        let cycle = |model: &mut Value| {
+            let edge = r#"{ "from": "softmax_0", "to": "l1", "weight": 1.0 }"#;
+            let edge = serde_json::from_str(edge).unwrap();
+            model["edges"].as_array_mut().unwrap().push(edge);
+        };
+        let constant_input = |model: &mut Value| model["input_nodes"][0] = Value::from("bias");
+        let disconnected_logit = |model: &mut Value| {
+            model["edges"]
+                .as_array_mut()
+                .unwrap()
+                .retain(|edge| !(edge["from"] == "l1" && edge["to"] == "softmax_0"));
+        };
+
+        for corrupt in &[
+            &cycle as &dyn Fn(&mut Value),
+            &constant_input,
+            &disconnected_logit,
+        ] {
+            let mut model = model.clone();
+            corrupt(&mut model);
+
+            let error = from_json(&model.to_string()).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -1,92 +1,1085 @@
+use builder::NetworkBuilder;
+use checkpoint;
+use checkpoint::TrainingProgress;
+use dot;
+use dot::DotOptions;
+use graph::{Graph, NodeId};
+use init::Initializer;
+use layers::to_rows;
 use layers::InputLayer;
 use layers::OutputLayer;
+use loss::MeanSquaredError;
+use model;
+use ndarray::Axis;
 use node::DerivativeCalculationParams;
 use node::Node;
+use node::WeightUpdateParams;
+use optimizer::{Optimizer, Sgd};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use regularization::Regularizer;
+use schedule::{Constant, LearningRateSchedule};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::f64;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
 
 /// Default usage:
 ///
 /// ```
+/// # use neural_network::network::NetworkConfigs;
 /// let configs = NetworkConfigs {
 ///     learning_rate: 0.0002,
-///     ..Default:default()
+///     ..Default::default()
 /// };
 /// ```
 pub struct NetworkConfigs {
     /// aka step size. See https://en.wikipedia.org/wiki/Stochastic_gradient_descent#Background
+    /// This is the base learning rate which is varied by `learning_rate_schedule`.
     /// Default: 0.0001
     pub learning_rate: f64,
+    /// Decides the learning rate of each weight update based on `learning_rate`.
+    /// Default: `Constant`
+    pub learning_rate_schedule: Box<dyn LearningRateSchedule>,
+    /// Used to draw the weights of connections made with `connect()`
+    /// when the `Network` is created.
+    /// Default: `Initializer::Uniform(-1.0, 1.0)`
+    pub initializer: Initializer,
+    /// Drives every random choice made by the network, i.e. weight initialization and
+    /// the order of training samples when `shuffle` is set. Identical seeds give identical
+    /// results when training on the same data.
+    /// Default: 0
+    pub seed: u64,
+    /// Whether to train on the training samples in a random order each epoch.
+    /// Default: false
+    pub shuffle: bool,
+    /// Number of training samples to accumulate gradients over before the weights are updated
+    /// with the mean gradient. 1 gives stochastic gradient descent, and a value equal to
+    /// or larger than the number of training samples gives full-batch gradient descent.
+    /// Any remaining samples at the end of an epoch make up a smaller batch.
+    /// Must be at least 1.
+    /// Default: 1
+    pub batch_size: usize,
+    /// Decides how weights are adjusted based on their gradients, e.g. `Sgd` or `Adam`.
+    /// Default: `Sgd`
+    pub optimizer: Box<dyn Optimizer>,
+    /// If set, the mean d(loss)/d(weight) of every weight is clipped to the range
+    /// `[-clip_value, clip_value]` before updating the weight.
+    /// Default: None
+    pub clip_value: Option<f64>,
+    /// If set, and the L2 norm of the mean d(loss)/d(weight) of all weights in the graph
+    /// exceeds `clip_norm`, all gradients are rescaled so that their norm equals `clip_norm`.
+    /// This is applied before `clip_value`.
+    /// Default: None
+    pub clip_norm: Option<f64>,
+    /// Penalty on the weights of all connections, which is added to the reported loss
+    /// and whose derivative is added to the weight gradients before each update.
+    /// Default: `Regularizer::None`
+    pub regularizer: Regularizer,
+    /// Node name: `Regularizer` used for the connections into that node instead of
+    /// `regularizer`.
+    /// Default: empty
+    pub node_regularizers: HashMap<String, Regularizer>,
+    /// Whether connections from `ConstantNode`s (i.e. biases) are penalised too.
+    /// Default: false
+    pub regularize_bias: bool,
 }
 
 impl Default for NetworkConfigs {
     fn default() -> NetworkConfigs {
         NetworkConfigs {
             learning_rate: 0.0001,
+            learning_rate_schedule: Box::new(Constant),
+            initializer: Default::default(),
+            seed: 0,
+            shuffle: false,
+            batch_size: 1,
+            optimizer: Box::new(Sgd),
+            clip_value: None,
+            clip_norm: None,
+            regularizer: Regularizer::None,
+            node_regularizers: HashMap::new(),
+            regularize_bias: false,
         }
     }
 }
 
-/// Representing the entire neural network graph
+impl NetworkConfigs {
+    /// Returns a new RNG seeded with `self.seed`, e.g. for generating synthetic training data
+    /// that is reproducible along with the network. The `Network` itself draws the weights and
+    /// shuffles the samples from streams of its own.
+    pub fn seeded_rng(&self) -> StdRng {
+        StdRng::seed_from_u64(self.seed)
+    }
+
+    /// The `Regularizer` that applies to the connection from `input_node` into `node`.
+    pub fn get_regularizer(&self, node: &dyn Node, input_node: &dyn Node) -> Regularizer {
+        if input_node.is_constant() && !self.regularize_bias {
+            return Regularizer::None;
+        }
+
+        *self
+            .node_regularizers
+            .get(node.name())
+            .unwrap_or(&self.regularizer)
+    }
+}
+
+/// Counts how often gradient clipping happened, see `NetworkConfigs.clip_value`
+/// and `NetworkConfigs.clip_norm`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ClipStats {
+    /// Number of individual weight gradients clipped by `clip_value`
+    pub clipped_values: usize,
+    /// Number of weight updates where all gradients were rescaled by `clip_norm`
+    pub clipped_norms: usize,
+}
+
+/// Comparison of one gradient calculated by backpropagation against its finite-difference
+/// approximation, see `Network.gradient_check()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GradientCheck {
+    /// Gradient calculated by backpropagation
+    pub analytic: f64,
+    /// Gradient approximated with central differences
+    pub numerical: f64,
+    /// |analytic - numerical| / max(|analytic|, |numerical|), or 0 if both are 0.
+    /// Typically below 1e-6 for correct derivatives, except at kinks such as ReLU's at 0.
+    pub relative_error: f64,
+}
+
+impl GradientCheck {
+    pub fn new(analytic: f64, numerical: f64) -> GradientCheck {
+        let scale = analytic.abs().max(numerical.abs());
+        let relative_error = if scale == 0.0 {
+            0.0
+        } else {
+            (analytic - numerical).abs() / scale
+        };
+
+        GradientCheck {
+            analytic,
+            numerical,
+            relative_error,
+        }
+    }
+}
+
+/// Result of `Network.gradient_check()`
+#[derive(Debug, Clone, Default)]
+pub struct GradientCheckReport {
+    /// (Input node name, receiving node name, index of the connection in
+    /// `Graph.input_weights()` of the receiving node): check of d(loss)/d(weight) of that
+    /// connection
+    pub weights: BTreeMap<(String, String, usize), GradientCheck>,
+    /// Input node name: check of d(loss)/d(input value)
+    pub inputs: BTreeMap<String, GradientCheck>,
+}
+
+impl GradientCheckReport {
+    /// The largest relative error of all weights and inputs
+    pub fn max_relative_error(&self) -> f64 {
+        self.weights
+            .values()
+            .chain(self.inputs.values())
+            .fold(0.0, |max, check| max.max(check.relative_error))
+    }
+}
 
+/// Problems with the structure of a network found by `Network.validate()`.
+///
+/// All nodes are referred to by name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    /// Groups of nodes that are connected in a cycle, which makes it impossible to order the
+    /// forward pass. Each group is the set of nodes of one strongly connected component.
+    pub cycles: Vec<Vec<String>>,
+    /// Nodes that have no path from any node of the `InputLayer`, so their activation never
+    /// depends on the inputs. Constant nodes (biases) are not included.
+    pub unreachable_nodes: Vec<String>,
+    /// Nodes whose output isn't connected to any node, but which aren't in the `OutputLayer`
+    /// either, so they have no loss gradient and don't affect the loss.
+    pub dangling_outputs: Vec<String>,
+    /// Nodes in the `OutputLayer` whose output is connected to other nodes. Their loss
+    /// gradient is ignored, as d(loss)/d(activation) is taken from their output nodes instead.
+    pub connected_output_nodes: Vec<String>,
+    /// (Input node name, receiving node name) of every pair of nodes
+    /// connected more than once
+    pub duplicate_edges: Vec<(String, String)>,
+}
+
+impl ValidationReport {
+    /// Whether no problems were found
+    pub fn is_valid(&self) -> bool {
+        self.cycles.is_empty()
+            && self.unreachable_nodes.is_empty()
+            && self.dangling_outputs.is_empty()
+            && self.connected_output_nodes.is_empty()
+            && self.duplicate_edges.is_empty()
+    }
+}
+
+/// Statistics of one pass over the training dataset, as returned by `Network.train_one_epoch()`
+#[derive(Debug, Clone)]
+pub struct EpochSummary {
+    /// Mean of the per-sample losses
+    pub mean_loss: f64,
+    pub min_loss: f64,
+    pub max_loss: f64,
+    /// Mean loss on the validation data set with `Network.set_validation_data()`,
+    /// evaluated at the end of the epoch.
+    pub validation_loss: Option<f64>,
+    /// Learning rate that the next epoch starts with
+    pub learning_rate: f64,
+    /// Gradient clipping that happened during the epoch
+    pub clip_stats: ClipStats,
+    /// Wall time taken to train the epoch
+    pub duration: Duration,
+}
+
+/// Representing the entire neural network graph
 pub struct Network {
+    /// All the nodes that make up this network
+    pub graph: Graph,
     pub input_layer: InputLayer,
     pub output_layer: OutputLayer,
     pub network_configs: NetworkConfigs,
+    /// Total number of training iterations (i.e. samples) that have been trained on.
+    /// Also used as the iteration passed to `evaluate_gradients()`, so that each training
+    /// iteration has a unique derivative calculation iteration.
+    pub iteration: usize,
+    /// Number of epochs trained with `train_one_epoch()`
+    pub epoch: usize,
+    /// Number of training samples whose gradients have been accumulated with
+    /// `accumulate_gradients()` since the last `update_weights()`.
+    pub batch_samples: usize,
+    /// Number of weight updates done with `update_weights()`
+    pub weight_updates: usize,
+    /// Gradient clipping that happened since the network was created
+    pub clip_stats: ClipStats,
+    /// Number of `gradient_check()` calls, used to give each check a unique
+    /// derivative calculation iteration that never collides with `iteration`.
+    gradient_checks: usize,
+    /// Flattened (inputs, ground truths) evaluated at the end of every epoch,
+    /// see `set_validation_data()`.
+    validation_data: Option<(Vec<f64>, Vec<f64>)>,
 }
 
 impl Network {
-    pub fn new(input_layer: InputLayer, output_layer: OutputLayer) -> Network {
-        Network {
+    /// `graph` should be the same `Graph` that the nodes of `input_layer` and
+    /// `output_layer` were created with.
+    pub fn new(graph: Graph, input_layer: InputLayer, output_layer: OutputLayer) -> Network {
+        Network::with_configs(graph, input_layer, output_layer, Default::default())
+    }
+
+    /// Same as `Network::new()`, but with custom `NetworkConfigs`.
+    ///
+    /// The weights of all connections made with `Graph.connect()` are drawn here with
+    /// `network_configs.initializer` and `network_configs.seed`, so this should be used over
+    /// `set_network_configs()` when using a custom initializer or seed.
+    ///
+    /// Panics if the graph contains a cycle, see `ValidationReport.cycles`.
+    pub fn with_configs(
+        graph: Graph,
+        input_layer: InputLayer,
+        output_layer: OutputLayer,
+        network_configs: NetworkConfigs,
+    ) -> Network {
+        assert_valid_configs(&network_configs);
+
+        let mut network = Network {
+            graph,
             input_layer,
             output_layer,
-            network_configs: Default::default(),
-        }
+            network_configs,
+            iteration: 0,
+            epoch: 0,
+            batch_samples: 0,
+            weight_updates: 0,
+            clip_stats: Default::default(),
+            gradient_checks: 0,
+            validation_data: None,
+        };
+
+        network.initialize_weights();
+
+        // Caught here rather than in the first forward pass
+        let cycles = network.validate().cycles;
+        assert!(
+            cycles.is_empty(),
+            "Graph contains a cycle! Nodes on a cycle: {:?}",
+            cycles
+        );
+
+        network
+    }
+
+    /// Start building a fully-connected network, see `NetworkBuilder`.
+    pub fn builder() -> NetworkBuilder {
+        NetworkBuilder::new()
     }
 
     pub fn set_network_configs(&mut self, network_configs: NetworkConfigs) {
+        assert_valid_configs(&network_configs);
         self.network_configs = network_configs;
     }
 
-    /// Calculate the average loss on the entire training dataset
-    pub fn calc_avg_training_loss(&self) {}
+    /// Set a dataset that is not part of the training dataset to be evaluated with `evaluate()`
+    /// at the end of every epoch. Its loss is reported in `EpochSummary.validation_loss`,
+    /// and is used by learning rate schedules such as `ReduceOnPlateau`.
+    ///
+    /// `inputs` and `ground_truths` are flattened arrays in the same format as the ones
+    /// accepted by `InputLayer::new()` and `OutputLayer::new()`.
+    pub fn set_validation_data(&mut self, inputs: &[f64], ground_truths: &[f64]) {
+        self.validation_data = Some((inputs.to_vec(), ground_truths.to_vec()));
+    }
+
+    /// The learning rate that will be used by the next weight update, as decided by
+    /// `NetworkConfigs.learning_rate_schedule`.
+    pub fn current_learning_rate(&self) -> f64 {
+        self.network_configs.learning_rate_schedule.learning_rate(
+            self.network_configs.learning_rate,
+            self.weight_updates,
+            self.epoch,
+        )
+    }
+
+    /// Checks the structure of the network for problems that would make training fail or
+    /// silently ignore parts of the network, see `ValidationReport`.
+    ///
+    /// `Network::with_configs()` already panics on a cycle, but one may still be introduced
+    /// by connecting nodes of `graph` afterwards, which makes `forward_pass()` panic.
+    pub fn validate(&self) -> ValidationReport {
+        let graph = &self.graph;
+        let name = |id: NodeId| graph.node(id).name().to_string();
+
+        let cycles = graph
+            .cycles()
+            .into_iter()
+            .map(|cycle| cycle.into_iter().map(name).collect())
+            .collect();
+
+        let reachable = graph.reachable_from(&self.input_layer.input_nodes);
+        let unreachable_nodes = graph
+            .node_ids()
+            .into_iter()
+            .filter(|&id| !reachable[id.index()] && !graph.node(id).is_constant())
+            .map(name)
+            .collect();
+
+        let is_output_node = |id: NodeId| self.output_layer.output_nodes.contains(&id);
+
+        let dangling_outputs = graph
+            .node_ids()
+            .into_iter()
+            .filter(|&id| graph.output_nodes(id).is_empty() && !is_output_node(id))
+            .map(name)
+            .collect();
+
+        let connected_output_nodes = self
+            .output_layer
+            .output_nodes
+            .iter()
+            .cloned()
+            .filter(|&id| !graph.output_nodes(id).is_empty())
+            .map(name)
+            .collect();
+
+        let mut duplicate_edges = vec![];
+        for id in graph.node_ids() {
+            let mut input_nodes = graph.input_nodes(id);
+            input_nodes.sort();
+
+            let mut prev = None;
+            for input_node in input_nodes {
+                let edge = (name(input_node), name(id));
+                if prev == Some(input_node) && duplicate_edges.last() != Some(&edge) {
+                    duplicate_edges.push(edge);
+                }
+                prev = Some(input_node);
+            }
+        }
+
+        ValidationReport {
+            cycles,
+            unreachable_nodes,
+            dangling_outputs,
+            connected_output_nodes,
+            duplicate_edges,
+        }
+    }
+
+    /// `Graph.remove_node()` for a node of this network.
+    ///
+    /// Panics if the node is part of the `InputLayer` or `OutputLayer`, as their ids would
+    /// no longer be valid.
+    pub fn remove_node(&mut self, name: &str) -> Option<Box<dyn Node + Send>> {
+        let id = self.graph.get_node_id(name)?;
+        self.assert_not_in_layers(id, name);
+
+        self.graph.remove_node(name)
+    }
+
+    /// `Graph.replace_node()` for a node of this network.
+    ///
+    /// Panics if the node is part of the `InputLayer` or `OutputLayer`, as the layers rely
+    /// on the kind of their nodes, e.g. `InputNode.set_value()`.
+    pub fn replace_node(&mut self, old: &str, new: NodeId) -> Option<Box<dyn Node + Send>> {
+        let id = self.graph.get_node_id(old)?;
+        self.assert_not_in_layers(id, old);
+
+        self.graph.replace_node(old, new)
+    }
+
+    fn assert_not_in_layers(&self, id: NodeId, name: &str) {
+        assert!(
+            !self.input_layer.input_nodes.contains(&id)
+                && !self.output_layer.output_nodes.contains(&id),
+            "[{}] Cannot remove or replace a node of the InputLayer or OutputLayer!",
+            name
+        );
+    }
+
+    /// Saves the nodes, connections and weights of this network, and the order of the nodes
+    /// of the input and output layers, as JSON. See `model` for the format.
+    ///
+    /// The training data, loss and `NetworkConfigs` aren't saved.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let json = model::to_json(
+            &self.graph,
+            &self.input_layer.input_nodes,
+            &self.output_layer.output_nodes,
+        );
+
+        fs::write(path, json)
+    }
+
+    /// Loads a network saved with `save()`, which gives identical activations for the
+    /// same input values.
+    ///
+    /// The loaded network has no training data, uses `MeanSquaredError` as the loss and
+    /// the default `NetworkConfigs`. Set `input_layer` and `output_layer` with the same
+    /// `input_layer.input_nodes` and `output_layer.output_nodes` to train it further.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Network> {
+        let json = fs::read_to_string(path)?;
+        let (graph, input_nodes, output_nodes) = model::from_json(&json)?;
+
+        let input_layer = InputLayer::new(&input_nodes, &[]);
+        let output_layer = OutputLayer::new(&output_nodes, &[], Box::new(MeanSquaredError));
+
+        Ok(Network::new(graph, input_layer, output_layer))
+    }
+
+    /// A Graphviz DOT digraph of the nodes reachable from the input nodes, with node names,
+    /// node kinds and edge weights, and optionally the latest activations and dloss values.
+    /// Edges are coloured by their gradient as of the last backward pass, see `dot::to_dot()`.
+    ///
+    /// Render it with e.g. `dot -Tsvg network.dot -o network.svg`.
+    pub fn to_dot(&self, options: DotOptions) -> String {
+        dot::to_dot(
+            &self.graph,
+            &self.input_layer.input_nodes,
+            &self.output_layer.output_nodes,
+            options,
+        )
+    }
+
+    /// Saves a checkpoint of the training progress in a compact binary format, which
+    /// `resume()` continues training from exactly where it stopped.
+    ///
+    /// Along with the graph, every weight's accumulated gradient and optimizer state, each
+    /// node's `TrainingState`, the seed, the epoch and iteration counters and the state of the
+    /// learning rate schedule are saved. See `checkpoint` for the format.
+    ///
+    /// Checkpoints are only exact at epoch boundaries, i.e. between calls of
+    /// `train_one_epoch()`. The position within an epoch isn't saved, so after samples were
+    /// trained on with `train_one_iteration()`, the next `train_one_epoch()` after resuming
+    /// trains on them again.
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let progress = TrainingProgress {
+            seed: self.network_configs.seed,
+            epoch: self.epoch,
+            iteration: self.iteration,
+            batch_samples: self.batch_samples,
+            weight_updates: self.weight_updates,
+            gradient_checks: self.gradient_checks,
+            clip_stats: self.clip_stats,
+            schedule_state: self.network_configs.learning_rate_schedule.state(),
+        };
+
+        let bytes = checkpoint::encode(
+            &self.graph,
+            &self.input_layer.input_nodes,
+            &self.output_layer.output_nodes,
+            &progress,
+        );
+
+        fs::write(path, bytes)
+    }
+
+    /// Replaces the graph and training progress of this network with a checkpoint saved
+    /// with `checkpoint()`.
+    ///
+    /// The training data, validation data, loss and `NetworkConfigs` aren't part of the
+    /// checkpoint, so this network should be created with the same ones as the network the
+    /// checkpoint was saved from, except that `NetworkConfigs.seed` is restored from the
+    /// checkpoint. Returns an error, leaving this network untouched, if the nodes of the input
+    /// and output layers don't match the checkpoint, or if its learning rate schedule state
+    /// doesn't have as many values as this network's schedule keeps, see
+    /// `LearningRateSchedule.set_state()`.
+    pub fn resume<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+        let (graph, input_nodes, output_nodes, progress) = checkpoint::decode(&bytes)?;
+
+        let layer_names = |graph: &Graph, ids: &[NodeId]| -> Vec<String> {
+            ids.iter()
+                .map(|&id| graph.node(id).name().to_string())
+                .collect()
+        };
+
+        for &(nodes, expected) in &[
+            (&input_nodes, &self.input_layer.input_nodes),
+            (&output_nodes, &self.output_layer.output_nodes),
+        ] {
+            let names = layer_names(&graph, nodes);
+            let expected = layer_names(&self.graph, expected);
+            if names != expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Checkpoint has layer nodes {:?}, expected {:?}",
+                        names, expected
+                    ),
+                ));
+            }
+        }
+
+        self.network_configs
+            .learning_rate_schedule
+            .set_state(&progress.schedule_state)?;
+
+        self.graph = graph;
+        self.input_layer.input_nodes = input_nodes;
+        self.output_layer.output_nodes = output_nodes;
+
+        self.network_configs.seed = progress.seed;
+        self.epoch = progress.epoch;
+        self.iteration = progress.iteration;
+        self.batch_samples = progress.batch_samples;
+        self.weight_updates = progress.weight_updates;
+        self.gradient_checks = progress.gradient_checks;
+        self.clip_stats = progress.clip_stats;
+
+        Ok(())
+    }
+
+    /// Draw the weights of the connections made with `Graph.connect()` that are yet to be
+    /// initialized, with `NetworkConfigs.initializer`.
+    ///
+    /// This is done when the `Network` is created and at the start of every forward pass, so
+    /// it only needs to be called to see the weights of connections made in between.
+    pub fn initialize_weights(&mut self) {
+        if !self.graph.has_pending_weights() {
+            return;
+        }
+
+        // `iteration + 1`, so that the weights don't reuse the stream of
+        // `NetworkConfigs.seeded_rng()`, which may have generated the training data
+        let stream = (self.iteration as u64 + 1).wrapping_mul(0xD1B5_4A32_D192_ED03);
+        let mut rng = StdRng::seed_from_u64(self.network_configs.seed ^ stream);
+
+        self.graph
+            .initialize_weights(&self.network_configs.initializer, &mut rng);
+    }
+
+    /// Calculate the activation of every node based on the current input values,
+    /// in topological order. Pending weights are drawn first, see `initialize_weights()`.
+    pub fn forward_pass(&mut self) {
+        self.initialize_weights();
+        self.graph.calc_activations();
+    }
+
+    /// Assign the input values of the training sample at index `iter` (which wraps around),
+    /// run the forward pass, and return the loss of the sample.
+    pub fn calc_iter_loss(&mut self, iter: usize) -> f64 {
+        self.input_layer.set_iteration(&mut self.graph, iter);
+        self.forward_pass();
+
+        self.output_layer.calculate_iter_loss(&self.graph, iter)
+    }
+
+    /// Calculate the average loss on the entire training dataset, including the
+    /// regularization penalty.
+    ///
+    /// This only runs forward passes, gradients and weights are left untouched.
+    pub fn calc_avg_training_loss(&mut self) -> f64 {
+        let sample_count = self.output_layer.training_ground_truths.len_of(Axis(0));
+        assert!(sample_count > 0, "The network has no training data!");
+
+        let mut total_loss = 0.0;
+        for iter in 0..sample_count {
+            total_loss += self.calc_iter_loss(iter);
+        }
+
+        total_loss / sample_count as f64 + self.calc_regularization_penalty()
+    }
+
+    /// Calculate the average loss on a dataset that is not part of the training dataset,
+    /// e.g. a validation set used to check for overfitting. The regularization penalty is
+    /// included.
+    ///
+    /// `inputs` and `ground_truths` are flattened arrays in the same format as the ones
+    /// accepted by `InputLayer::new()` and `OutputLayer::new()`.
+    ///
+    /// This only runs forward passes, gradients and weights are left untouched.
+    pub fn evaluate(&mut self, inputs: &[f64], ground_truths: &[f64]) -> f64 {
+        let inputs = to_rows(inputs, self.input_layer.input_nodes.len());
+        let ground_truths = to_rows(ground_truths, self.output_layer.output_nodes.len());
+
+        let sample_count = inputs.len_of(Axis(0));
+        assert_eq!(
+            sample_count,
+            ground_truths.len_of(Axis(0)),
+            "inputs and ground_truths must have the same number of samples!"
+        );
+        assert!(sample_count > 0, "inputs must not be empty!");
+
+        let mut total_loss = 0.0;
+        for (input_vals, ground_truth_vals) in inputs.outer_iter().zip(ground_truths.outer_iter()) {
+            self.input_layer
+                .set_input_values(&mut self.graph, &input_vals.to_vec());
+            self.forward_pass();
+            total_loss += self
+                .output_layer
+                .calculate_loss(&self.graph, &ground_truth_vals.to_vec());
+        }
+
+        total_loss / sample_count as f64 + self.calc_regularization_penalty()
+    }
+
+    /// Sum of the `Regularizer` penalties of all weights in the graph, as configured by
+    /// `NetworkConfigs.regularizer`, `NetworkConfigs.node_regularizers` and
+    /// `NetworkConfigs.regularize_bias`.
+    pub fn calc_regularization_penalty(&self) -> f64 {
+        let configs = &self.network_configs;
+        let mut penalty = 0.0;
+
+        self.graph.visit_weights(|node, input_node, nw| {
+            let regularizer = configs.get_regularizer(node, input_node);
+            penalty += regularizer.penalty(nw.weight);
+        });
+
+        penalty
+    }
 
     /// Traverse through all the nodes in the network and evaluate d(loss) / d(node activation)
     /// for each one of them, storing them in the `TrainingState.dloss` field which can be
     /// retrieved with `Node.get_training_state()` or `Node.get_training_state_mut()`.
     ///
-    /// `iteration`: The training iteration.
-    /// `output_nodes_loss_fn_derivative`: Fn(Node name, node activation) -> derivative partial term
-    pub fn evaluate_gradients(
+    /// The input nodes must be assigned the values of the training sample, and the
+    /// activations of all nodes calculated with `forward_pass()` first.
+    ///
+    /// `iteration`: The training iteration. Must differ from the previous call's.
+    /// `ground_truths`: The ground truth of each output node, in the same index order as
+    /// `OutputLayer.output_nodes`.
+    /// `loss_fn_derivative`: Fn(output node activations, ground truths) -> d(loss) / d(activation)
+    /// of each output node, e.g. the gradient given by `Loss.loss_and_gradient()`.
+    pub fn evaluate_gradients<F>(
         &mut self,
         iteration: i32,
-        output_nodes_loss_fn_derivative: impl Fn(&str) -> f64 + 'static,
-    ) {
-        self.input_layer.set_iteration(iteration as usize);
+        ground_truths: &[f64],
+        loss_fn_derivative: F,
+    ) where
+        F: Fn(&[f64], &[f64]) -> Vec<f64>,
+    {
+        backpropagate(
+            &mut self.graph,
+            &self.input_layer,
+            &self.output_layer,
+            iteration,
+            ground_truths,
+            loss_fn_derivative,
+        );
+    }
+
+    /// `evaluate_gradients()` of the training sample at index `sample` with the
+    /// `OutputLayer.loss`, whose activations must already be calculated.
+    fn evaluate_iter_gradients(&mut self, iteration: i32, sample: usize) {
+        let ground_truths = self.output_layer.get_iter_ground_truths(sample);
+        let loss_fn = &self.output_layer.loss;
 
-        let derivative_calc_params = DerivativeCalculationParams::new(
+        backpropagate(
+            &mut self.graph,
+            &self.input_layer,
+            &self.output_layer,
             iteration,
-            self.output_layer
-                .output_nodes
-                .iter()
-                .map(|x| x.lock().unwrap().name().to_string())
-                .collect(),
-            output_nodes_loss_fn_derivative,
+            &ground_truths,
+            |activations, truths| loss_fn.loss_and_gradient(activations, truths).1,
         );
-        for n in &self.input_layer.input_nodes {
-            let mut n = n.lock().unwrap();
-            n.calc_activation_derivative(&derivative_calc_params);
+    }
+
+    /// Accumulate d(loss) / d(weight) of every connection based on the gradients
+    /// previously calculated with `evaluate_gradients()`, adding the current training
+    /// sample to the batch.
+    ///
+    /// `iteration`: The iteration that was passed to `evaluate_gradients()`.
+    pub fn accumulate_gradients(&mut self, iteration: i32) {
+        self.graph.accumulate_gradients(iteration);
+
+        self.batch_samples += 1;
+    }
+
+    /// Update each node's weights based on the mean of the gradients accumulated with
+    /// `accumulate_gradients()` since the last update, plus the derivative of the
+    /// regularization penalty, clipping the gradients as per `NetworkConfigs.clip_value`
+    /// and `NetworkConfigs.clip_norm`.
+    /// Note that `accumulate_gradients()` must be called first.
+    pub fn update_weights(&mut self) {
+        if self.batch_samples == 0 {
+            return;
+        }
+
+        // The penalty is part of the loss of every sample in the batch, so its derivative
+        // is accumulated once per sample as well.
+        let batch_samples = self.batch_samples as f64;
+        let configs = &self.network_configs;
+        self.graph.visit_weights_mut(|node, input_node, nw| {
+            let regularizer = configs.get_regularizer(node, input_node);
+            nw.gradient += batch_samples * regularizer.gradient(nw.weight);
+        });
+
+        let mut gradient_scale = 1.0;
+        if let Some(clip_norm) = self.network_configs.clip_norm {
+            let norm = self.calc_gradient_norm();
+            if norm > clip_norm {
+                gradient_scale = clip_norm / norm;
+                self.clip_stats.clipped_norms += 1;
+            }
         }
+
+        let params = WeightUpdateParams {
+            step_size: self.current_learning_rate(),
+            optimizer: &*self.network_configs.optimizer,
+            batch_size: self.batch_samples,
+            gradient_scale,
+            clip_value: self.network_configs.clip_value,
+            clipped_gradients: Cell::new(0),
+        };
+
+        self.graph.update_weights(&params);
+
+        self.clip_stats.clipped_values += params.clipped_gradients.get();
+        self.batch_samples = 0;
+        self.weight_updates += 1;
     }
 
-    /// Update each node's weights based on its previously calculated gradients.
-    /// Note that `evaluate_gradients()` must be called first.
-    pub fn update_weights(&mut self) {}
+    /// Calculates the L2 norm of the mean d(loss)/d(weight) of all weights in the graph,
+    /// based on the gradients accumulated since the last update.
+    pub fn calc_gradient_norm(&self) -> f64 {
+        let batch_size = self.batch_samples.max(1) as f64;
+        let mut sum_of_squares = 0.0;
+
+        self.graph.visit_weights(|_, _, nw| {
+            sum_of_squares += (nw.gradient / batch_size).powi(2);
+        });
+
+        sum_of_squares.sqrt()
+    }
+
+    /// Verifies the derivatives of every node by comparing d(loss)/d(weight) of every connection
+    /// and d(loss)/d(input value) of every input node, as calculated by backpropagation,
+    /// against central differences of the loss on the training sample at index `sample`,
+    /// where each weight or input is perturbed by `epsilon`.
+    ///
+    /// The regularization penalty is not part of the checked loss. Weights, accumulated
+    /// gradients and the iteration counter are left untouched.
+    pub fn gradient_check(&mut self, sample: usize, epsilon: f64) -> GradientCheckReport {
+        let mut report = GradientCheckReport::default();
+
+        // Backpropagate this sample
+        self.calc_iter_loss(sample);
+
+        self.gradient_checks += 1;
+        let iteration = -1 - self.gradient_checks as i32;
+        self.evaluate_iter_gradients(iteration, sample);
+
+        // Collect d(loss)/d(weight) of this sample alone, putting back the gradients
+        // accumulated so far afterwards.
+        let mut accumulated = vec![];
+        self.graph.visit_weights_mut(|_, _, nw| {
+            accumulated.push(nw.gradient);
+            nw.gradient = 0.0;
+        });
+
+        self.graph.accumulate_gradients(iteration);
+
+        let mut analytic = vec![];
+        let mut accumulated = accumulated.into_iter();
+        self.graph.visit_weights_mut(|_, _, nw| {
+            analytic.push(nw.gradient);
+            nw.gradient = accumulated.next().unwrap();
+        });
+
+        let mut analytic = analytic.into_iter();
+        for id in self.graph.node_ids() {
+            if !self.graph.node(id).is_weighted() {
+                continue;
+            }
+
+            for idx in 0..self.graph.input_weights(id).len() {
+                let analytic = analytic.next().unwrap();
+                let numerical = self.calc_numerical_gradient(
+                    sample,
+                    epsilon,
+                    |graph| graph.input_weights(id)[idx].weight,
+                    |graph, weight| graph.input_weights_mut(id)[idx].weight = weight,
+                );
+
+                let input_node = self.graph.input_weights(id)[idx].node;
+                report.weights.insert(
+                    (
+                        self.graph.node(input_node).name().to_string(),
+                        self.graph.node(id).name().to_string(),
+                        idx,
+                    ),
+                    GradientCheck::new(analytic, numerical),
+                );
+            }
+        }
+
+        for input_node in self.input_layer.input_nodes.clone() {
+            let analytic = self.graph.node(input_node).get_training_state().dloss;
+
+            let numerical = self.calc_numerical_gradient(
+                sample,
+                epsilon,
+                |graph| graph.node(input_node).get_last_calc_activation(),
+                |graph, value| graph.node_mut(input_node).set_value(value),
+            );
+
+            report.inputs.insert(
+                self.graph.node(input_node).name().to_string(),
+                GradientCheck::new(analytic, numerical),
+            );
+        }
+
+        report
+    }
+
+    /// (loss(x + epsilon) - loss(x - epsilon)) / (2 * epsilon) on the training sample at
+    /// index `sample`, where `get(graph)` reads x and `set(graph, x)` writes it.
+    ///
+    /// The input values of `sample` must already be assigned. The original x is written back
+    /// afterwards, rather than undoing the perturbation, so that it is restored exactly.
+    fn calc_numerical_gradient<G, S>(&mut self, sample: usize, epsilon: f64, get: G, set: S) -> f64
+    where
+        G: Fn(&Graph) -> f64,
+        S: Fn(&mut Graph, f64),
+    {
+        let x = get(&self.graph);
+
+        set(&mut self.graph, x + epsilon);
+        self.forward_pass();
+        let loss_plus = self.output_layer.calculate_iter_loss(&self.graph, sample);
+
+        set(&mut self.graph, x - epsilon);
+        self.forward_pass();
+        let loss_minus = self.output_layer.calculate_iter_loss(&self.graph, sample);
+
+        set(&mut self.graph, x);
+        self.forward_pass();
+
+        (loss_plus - loss_minus) / (2.0 * epsilon)
+    }
+
+    /// Run one forward pass and backward pass on the training sample at index `sample`,
+    /// then advance the iteration counter. The weights are updated once
+    /// `NetworkConfigs.batch_size` samples have been accumulated.
+    ///
+    /// Returns the loss of the sample before the weights were updated, including the
+    /// regularization penalty.
+    pub fn train_one_iteration(&mut self, sample: usize) -> f64 {
+        let loss = self.calc_iter_loss(sample) + self.calc_regularization_penalty();
+
+        let iteration = self.iteration as i32;
+        self.evaluate_iter_gradients(iteration, sample);
+        self.accumulate_gradients(iteration);
+
+        if self.batch_samples >= self.network_configs.batch_size {
+            self.update_weights();
+        }
+
+        self.iteration += 1;
+
+        loss
+    }
 
     /// 1 epoch = go through all of the training data once.
-    pub fn train_one_epoch(&mut self) {
-        // Iteration represents the training sample index
+    ///
+    /// Each training sample is put through `train_one_iteration()`, i.e. a forward pass,
+    /// backward pass and weight update once per batch. If `NetworkConfigs.shuffle` is set, the samples
+    /// are visited in a random order determined by the seed and the epoch number.
+    ///
+    /// Panics if the network has no training data, e.g. after `Network::load()`.
+    pub fn train_one_epoch(&mut self) -> EpochSummary {
+        let start = Instant::now();
+        let clip_stats_before = self.clip_stats;
+
+        let sample_count = self.input_layer.training_inputs.len_of(Axis(0));
+        assert!(
+            sample_count > 0,
+            "The network has no training data! See `InputLayer::new()`."
+        );
+
+        let mut samples: Vec<usize> = (0..sample_count).collect();
+        if self.network_configs.shuffle {
+            self.epoch_rng().shuffle(&mut samples);
+        }
+
+        let mut total_loss = 0.0;
+        let mut min_loss = f64::INFINITY;
+        let mut max_loss = f64::NEG_INFINITY;
+
+        for sample in samples {
+            let loss = self.train_one_iteration(sample);
 
-        for iter in 0..self.input_layer.training_inputs.len() {
-            self.input_layer.set_iteration(iter);
+            total_loss += loss;
+            min_loss = min_loss.min(loss);
+            max_loss = max_loss.max(loss);
         }
+
+        // Don't carry an incomplete batch over to the next epoch
+        self.update_weights();
+
+        let mean_loss = total_loss / sample_count as f64;
+
+        let validation_loss = match self.validation_data.take() {
+            Some((inputs, ground_truths)) => {
+                let loss = self.evaluate(&inputs, &ground_truths);
+                self.validation_data = Some((inputs, ground_truths));
+                Some(loss)
+            }
+            None => None,
+        };
+
+        self.network_configs
+            .learning_rate_schedule
+            .on_epoch_end(self.epoch, validation_loss.unwrap_or(mean_loss));
+
+        self.epoch += 1;
+
+        EpochSummary {
+            mean_loss,
+            min_loss,
+            max_loss,
+            validation_loss,
+            learning_rate: self.current_learning_rate(),
+            clip_stats: ClipStats {
+                clipped_values: self.clip_stats.clipped_values - clip_stats_before.clipped_values,
+                clipped_norms: self.clip_stats.clipped_norms - clip_stats_before.clipped_norms,
+            },
+            duration: start.elapsed(),
+        }
+    }
+
+    /// RNG for random choices made during the current epoch.
+    ///
+    /// Derived from only the seed and the epoch number, so that each epoch is
+    /// reproducible on its own. Mixes in `epoch + 1` so that no epoch reuses the stream of
+    /// `NetworkConfigs.seeded_rng()`.
+    fn epoch_rng(&self) -> StdRng {
+        let stream = (self.epoch as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        StdRng::seed_from_u64(self.network_configs.seed ^ stream)
+    }
+}
+
+fn assert_valid_configs(network_configs: &NetworkConfigs) {
+    assert!(
+        network_configs.batch_size > 0,
+        "NetworkConfigs.batch_size must be at least 1!"
+    );
+}
+
+/// Shared by `Network.evaluate_gradients()` and `Network.evaluate_iter_gradients()`, taking the
+/// parts of the `Network` separately so that `loss_fn_derivative` may borrow the `OutputLayer`.
+fn backpropagate<F>(
+    graph: &mut Graph,
+    input_layer: &InputLayer,
+    output_layer: &OutputLayer,
+    iteration: i32,
+    ground_truths: &[f64],
+    loss_fn_derivative: F,
+) where
+    F: Fn(&[f64], &[f64]) -> Vec<f64>,
+{
+    let derivative_calc_params = DerivativeCalculationParams::new(
+        iteration,
+        &output_layer.output_nodes,
+        &output_layer.get_last_activations(graph),
+        ground_truths,
+        loss_fn_derivative,
+    );
+    graph.calc_activation_derivatives(&input_layer.input_nodes, &derivative_calc_params);
+}
+
+#[cfg(test)]
+mod tests {
+    use activation::Activation;
+    use graph::Graph;
+    use layers::{InputLayer, OutputLayer};
+    use loss::CategoricalCrossEntropy;
+    use network::Network;
+    use node::{ActivationNode, ConstantNode, InputNode, SigmoidNode, SumNode};
+    use softmax::SoftmaxGroup;
+
+    #[test]
+    fn gradient_check_matches_backpropagation() {
+        let mut graph = Graph::new();
+
+        let i1 = InputNode::new(&mut graph, "i1", 0.0);
+        let i2 = InputNode::new(&mut graph, "i2", 0.0);
+        let bias = ConstantNode::new(&mut graph, "bias", 1.0);
+        let h1 = ActivationNode::new(&mut graph, "h1", Activation::Tanh);
+        let h2 = ActivationNode::new(&mut graph, "h2", Activation::Softplus);
+        let h3 = SigmoidNode::new(&mut graph, "h3");
+        let l1 = SumNode::new(&mut graph, "l1");
+        let l2 = SumNode::new(&mut graph, "l2");
+
+        for &hidden in &[h1, h2, h3] {
+            for &input in &[i1, i2, bias] {
+                graph.connect(input, hidden);
+            }
+        }
+        // A duplicate connection, checked separately
+        graph.connect(i1, h1);
+        for &logit in &[l1, l2] {
+            for &input in &[h1, h2, h3, bias] {
+                graph.connect(input, logit);
+            }
+        }
+
+        let softmax = SoftmaxGroup::new(&mut graph, "softmax", &[l1, l2]);
+
+        let input_layer = InputLayer::new(&[i1, i2], &[0.3, -1.2]);
+        let output_layer = OutputLayer::new(
+            &softmax.nodes,
+            &[1.0, 0.0],
+            Box::new(CategoricalCrossEntropy::default()),
+        );
+        let mut network = Network::new(graph, input_layer, output_layer);
+
+        let report = network.gradient_check(0, 1e-5);
+
+        // Softmax nodes aren't weighted
+        assert_eq!(report.weights.len(), 4 + 3 + 3 + 2 * 4);
+        assert!(report
+            .weights
+            .contains_key(&("i1".to_string(), "h1".to_string(), 3)));
+        assert!(report.max_relative_error() < 1e-6, "{:?}", report);
     }
 }
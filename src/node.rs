@@ -2,82 +2,277 @@
 //! Just some basic nodes
 //!
 
-use am;
-use rand::prelude::*;
+use activation::Activation;
+use graph::{Graph, NodeId};
+use optimizer::{Optimizer, OptimizerState};
+use softmax::SoftmaxNode;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::f64;
-use std::sync::Mutex;
-use AM;
+use std::io;
 
-lazy_static! {
-    /// Global lookup for nodes
-    pub static ref NODES: Mutex<HashMap<String, AM<dyn Node + Send>>> = Mutex::new(HashMap::new());
-}
-
-/// Call this in the constructor of nodes
-fn register_node(name: &str, node: AM<dyn Node + Send>) {
-    if let Some(_) = NODES.lock().unwrap().insert(name.to_string(), node) {
-        panic!("Cannot create two nodes with same name! [{}]", name);
-    }
-}
-
-/// This object is passed as a constant parameter through the recursive
-/// `calc_activation_derivative` function.
+/// This object is passed as a constant parameter to
+/// `Graph.calc_activation_derivatives()`.
 pub struct DerivativeCalculationParams {
     /// The calculation iteration
     calc_derivative_iteration: i32,
-    /// Node name: partial derivative of loss function
-    ///
-    /// This method is used when the loss function can be expressed as a sum
-    /// of single-argument functions, e.g., loss = f(a) + f(b) + f(c) + etc...
-    /// where a, b, c, ... are activation values of final layer nodes, as in this case
-    /// the derivative of the loss function against each output node's activation value
-    /// can be simply expressed as f'(a), f'(b), f'(c), etc...
+    /// Output node: d(loss) / d(output node activation)
     ///
-    /// The values in this hashmap represent the output of the functions f'(a), f'(b), f'(c), etc...
-    output_nodes_loss_fn_derivative: HashMap<String, f64>,
+    /// The whole gradient is calculated at once from all the output node activations and
+    /// ground truths, so the loss function need not be a sum of single-argument functions,
+    /// e.g. softmax cross-entropy or cosine distance.
+    output_nodes_loss_fn_derivative: HashMap<NodeId, f64>,
 }
 
 impl DerivativeCalculationParams {
+    /// `output_layer_nodes`, `activations` and `ground_truths` are in the same index
+    /// order, i.e. the order of `OutputLayer.output_nodes`.
+    ///
+    /// `derivative_fn`: Fn(activations, ground truths) -> d(loss) / d(activation) of each
+    /// output node, in the same index order.
     pub fn new<F>(
         calc_derivative_iteration: i32,
-        output_layer_node_names: Vec<String>,
+        output_layer_nodes: &[NodeId],
+        activations: &[f64],
+        ground_truths: &[f64],
         derivative_fn: F,
     ) -> DerivativeCalculationParams
     where
-        F: 'static + Fn(&str) -> f64,
+        F: Fn(&[f64], &[f64]) -> Vec<f64>,
     {
-        let mut output_nodes_loss_fn_derivative: HashMap<String, f64> = HashMap::new();
+        assert_eq!(
+            activations.len(),
+            output_layer_nodes.len(),
+            "Expected one activation value per output node!"
+        );
+        assert_eq!(
+            ground_truths.len(),
+            output_layer_nodes.len(),
+            "Expected one ground truth value per output node!"
+        );
 
-        for n in output_layer_node_names {
-            let n_clone = n.clone();
-            output_nodes_loss_fn_derivative.insert(n.clone(), derivative_fn(n_clone.as_str()));
-        }
+        let gradient = derivative_fn(activations, ground_truths);
+        assert_eq!(
+            gradient.len(),
+            output_layer_nodes.len(),
+            "Expected the loss function derivative to give one value per output node!"
+        );
+
+        let output_nodes_loss_fn_derivative =
+            output_layer_nodes.iter().cloned().zip(gradient).collect();
 
         DerivativeCalculationParams {
             calc_derivative_iteration,
             output_nodes_loss_fn_derivative,
         }
     }
+
+    pub fn calc_derivative_iteration(&self) -> i32 {
+        self.calc_derivative_iteration
+    }
+
+    /// d(loss) / d(activation) of `node`, if it is an output node.
+    pub fn get_loss_fn_derivative(&self, node: NodeId) -> Option<f64> {
+        self.output_nodes_loss_fn_derivative.get(&node).cloned()
+    }
+}
+
+/// This object is passed as a constant parameter to `update_input_weights()`
+/// for the input connections of every node.
+pub struct WeightUpdateParams<'a> {
+    /// The multiplier of the gradient to adjust the weights by, i.e. the learning rate.
+    pub step_size: f64,
+    /// Decides how each weight is adjusted given its gradient and step size.
+    pub optimizer: &'a dyn Optimizer,
+    /// The number of training samples whose gradients were accumulated since the last update.
+    /// The accumulated gradients are divided by this to get the mean gradient of the batch.
+    pub batch_size: usize,
+    /// Every mean gradient is multiplied by this before being clipped and passed to the
+    /// optimizer. Used for clipping by the global norm of all gradients.
+    pub gradient_scale: f64,
+    /// If set, every mean gradient is clipped to the range `[-clip_value, clip_value]`.
+    pub clip_value: Option<f64>,
+    /// Incremented for every gradient clipped by `clip_value`.
+    pub clipped_gradients: Cell<usize>,
+}
+
+/// The activation and weight of one input connection of a node, as passed to the
+/// methods of `Node` by the `Graph`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeInput {
+    /// The input node
+    pub node: NodeId,
+    /// The last calculated activation of the input node
+    pub activation: f64,
+    /// The weight of the connection
+    pub weight: f64,
+}
+
+/// The type of a node along with its parameters, which is enough to create the node again,
+/// e.g. when loading a saved network.
+///
+/// `L` refers to the logit of a softmax node, which is its `NodeId` except in the records
+/// passed to `create_nodes()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind<L = NodeId> {
+    /// `InputNode` with its current value
+    Input(f64),
+    /// `ConstantNode` with its constant value
+    Constant(f64),
+    /// `SumNode`
+    Sum,
+    /// `SigmoidNode`
+    Sigmoid,
+    /// `ActivationNode` with its activation function
+    Activation(Activation),
+    /// `SoftmaxNode`
+    Softmax { logit: L, fused_cross_entropy: bool },
+}
+
+impl NodeKind {
+    /// Creates a node of this kind in `graph`, as its constructor would.
+    pub fn create(self, graph: &mut Graph, name: &str) -> NodeId {
+        match self {
+            NodeKind::Input(value) => InputNode::new(graph, name, value),
+            NodeKind::Constant(value) => ConstantNode::new(graph, name, value),
+            NodeKind::Sum => SumNode::new(graph, name),
+            NodeKind::Sigmoid => SigmoidNode::new(graph, name),
+            NodeKind::Activation(activation_fn) => ActivationNode::new(graph, name, activation_fn),
+            NodeKind::Softmax {
+                logit,
+                fused_cross_entropy,
+            } => SoftmaxNode::new(graph, name, logit, fused_cross_entropy),
+        }
+    }
+}
+
+impl<L> NodeKind<L> {
+    /// Converts how the logit of a softmax node is referred to with `f`.
+    pub fn map_logit<M, E, F>(self, f: F) -> Result<NodeKind<M>, E>
+    where
+        F: FnOnce(L) -> Result<M, E>,
+    {
+        Ok(match self {
+            NodeKind::Input(value) => NodeKind::Input(value),
+            NodeKind::Constant(value) => NodeKind::Constant(value),
+            NodeKind::Sum => NodeKind::Sum,
+            NodeKind::Sigmoid => NodeKind::Sigmoid,
+            NodeKind::Activation(activation_fn) => NodeKind::Activation(activation_fn),
+            NodeKind::Softmax {
+                logit,
+                fused_cross_entropy,
+            } => NodeKind::Softmax {
+                logit: f(logit)?,
+                fused_cross_entropy,
+            },
+        })
+    }
+}
+
+/// Creates a node in `graph` from each (name, kind) record of a saved network, where the
+/// logit of a softmax node is the index of its record.
+///
+/// Returns the id of each node in the same order as `records`.
+pub(crate) fn create_nodes(
+    graph: &mut Graph,
+    records: &[(String, NodeKind<usize>)],
+) -> io::Result<Vec<NodeId>> {
+    let mut ids: Vec<Option<NodeId>> = vec![None; records.len()];
+
+    // Softmax nodes whose logit comes after them are created last,
+    // as the logit needs to exist first
+    let (now, deferred): (Vec<usize>, Vec<usize>) =
+        (0..records.len()).partition(|&idx| match records[idx].1 {
+            NodeKind::Softmax { logit, .. } => logit < idx,
+            _ => true,
+        });
+
+    for idx in now.into_iter().chain(deferred) {
+        let (ref name, kind) = records[idx];
+
+        if graph.contains_node(name) {
+            return Err(invalid_data(format!("Duplicate node name [{}]", name)));
+        }
+
+        let kind = kind.map_logit(|logit| match ids.get(logit) {
+            Some(&Some(id)) => Ok(id),
+            Some(&None) => Err(invalid_data(format!("[{}] Logit is a softmax node", name))),
+            None => Err(invalid_data(format!("[{}] Unknown logit {}", name, logit))),
+        })?;
+
+        ids[idx] = Some(kind.create(graph, name));
+    }
+
+    // every node has been created
+    Ok(ids.into_iter().map(Option::unwrap).collect())
+}
+
+/// Checks what `Network` relies on but a saved network or checkpoint can violate: the
+/// graph has no cycles, `input_nodes` are `InputNode`s, and every softmax node is connected
+/// to its own logit.
+pub(crate) fn check_structure(graph: &Graph, input_nodes: &[NodeId]) -> io::Result<()> {
+    if let Some(cycle) = graph.cycles().first() {
+        let names: Vec<&str> = cycle.iter().map(|&id| graph.node(id).name()).collect();
+        return Err(invalid_data(format!("Cycle through nodes {:?}", names)));
+    }
+
+    for &id in input_nodes {
+        match graph.node(id).kind() {
+            NodeKind::Input(_) => {}
+            _ => {
+                return Err(invalid_data(format!(
+                    "[{}] Input node is not an InputNode",
+                    graph.node(id).name()
+                )))
+            }
+        }
+    }
+
+    for id in graph.node_ids() {
+        if let NodeKind::Softmax { logit, .. } = graph.node(id).kind() {
+            if !graph.input_nodes(id).contains(&logit) {
+                return Err(invalid_data(format!(
+                    "[{}] Own logit [{}] is not connected",
+                    graph.node(id).name(),
+                    graph.node(logit).name()
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Error of malformed saved networks and checkpoints
+pub(crate) fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
 }
 
 /// The generic node trait
+///
+/// Nodes are stored in a `Graph`, which owns the connections between them. Methods which
+/// depend on the inputs of the node are passed `inputs`, the `NodeInput` of each input
+/// connection in the order they were connected.
 pub trait Node {
     /// Retrieve a node's unique identifier
     fn name(&self) -> &str;
 
+    /// Retrieves the type and parameters of the node, from which `NodeKind.create()` can
+    /// create it again.
+    fn kind(&self) -> NodeKind;
+
     /// 'Activation' refers to the output value of the node.
     ///
     /// This should also update the stored activation value which will be returned by
     /// `get_last_calc_activation()`
-    fn calc_activation(&mut self) -> f64;
+    fn calc_activation(&mut self, inputs: &[NodeInput]) -> f64;
 
     /// Retrieves the last calculated activation.
     ///
     /// This cached value should be updated every time `calc_activation` is called. However,
     /// there is no need to call `calc_activation` on each of the nodes directly, instead
-    /// calling `calculate_iter_loss()` on the `OutputLayer` object will make all connected nodes
-    /// update their cached activation values.
+    /// calling `Graph.calc_activations()` will update the cached activation values of all nodes
+    /// in topological order.
     ///
     /// This is useful when trying to calculate node determinants and the node
     /// activation value is needed such as
@@ -89,141 +284,43 @@ pub trait Node {
 
     fn get_training_state_mut(&mut self) -> &mut TrainingState;
 
-    /// Calculates the derivative of the loss function against
-    /// this node's activation value (as per `get_last_calc_activation()`), then stores the
-    /// derivative for later use when updating the nodes.
+    /// Calculates the value of d(self activation) / d(inputs[input_index] activation)
     ///
-    /// Note that the cached activation values from `get_last_calc_activation()` is updated
-    /// by calling
-    ///
-    /// This is a recursive function which starts off by invoking this method on all
-    /// input nodes, then follows by calling this method on all of its `output_nodes()`.
-    /// Of course, this will lead to numerous unnecessary recalculations of nodes that share the
-    /// same set of multiple children (such as in fully-connected networks)
-    ///
-    /// As such, a derivative calculation iteration counter is stored in the DerivativeCalculationParams
-    /// parameter which should be compared to a local DerivativeCalculationParams store which updates per call,
-    /// if this function has been called on the same object twice, the calculation iteration id
-    /// would have been found to be the same and the derivative calculation and recursion of
-    /// its output nodes can be skipped.
-    fn calc_activation_derivative(&mut self, calc_state: &DerivativeCalculationParams) -> f64 {
-        if self.get_training_state().calc_derivative_iteration
-            != calc_state.calc_derivative_iteration
-        {
-            /*
-                Simply sum up partial derivatives of each output node.
-                Let x            -> this activation
-                Let a, b, c, ... -> three output nodes receiving this node activation as input
-
-                d(loss) / d(self.activation) = da/dx * dL/da + db/dx * dL/db + dc/dx * dL/dc + etc...
-            */
-
-            self.get_training_state_mut().dloss = 0.0;
-
-            let output_nodes_count = { self.output_nodes().len() };
-
-            if output_nodes_count != 0 {
-                let mut final_dloss = 0.0;
-                for o in self.output_nodes() {
-                    let mut o = o.lock().unwrap();
-                    let dloss_partial_derivative = o.calc_derivative_against(self.name())
-                        * o.calc_activation_derivative(&calc_state);
-
-                    final_dloss += dloss_partial_derivative;
-                }
-
-                self.get_training_state_mut().dloss = final_dloss;
-            } else if let Some(derivative) =
-                calc_state.output_nodes_loss_fn_derivative.get(self.name())
-            {
-                // If there are no output nodes, check calc_state if this node is an output node
-                // with a given partial loss function
-
-                self.get_training_state_mut().dloss = *derivative;
-            } else {
-                println!(
-                    "WARNING: [{}] Last layer node found that doesn't have a registered loss \
-                     function partial derivative, defaulting gradient to 0.",
-                    self.name()
-                );
-            }
-        }
-
-        println!(
-            "dLoss/d[{}]: {}",
-            self.name(),
-            self.get_training_state().dloss
-        );
+    /// `Graph.calc_activation_derivatives()` uses this to pass d(loss) / d(self activation) on
+    /// to the input nodes.
+    fn calc_derivative_against(&self, inputs: &[NodeInput], input_index: usize) -> f64;
 
-        self.get_training_state_mut().dloss
+    /// Calculates the value of d(self activation) / d(inputs[input_index] weight), which is
+    /// used to accumulate the gradient of each weight in `Graph.accumulate_gradients()`.
+    fn calc_derivative_against_weight(&self, _inputs: &[NodeInput], _input_index: usize) -> f64 {
+        // default to activations that don't depend on weights
+        0.0
     }
 
-    /// Calculates the value of d(self activation) / d(input_node activation)
-    /// assuming that `input_node` is an immediate input to the input_node
-    ///
-    /// This is the consumer function for the recursive `calc_activation_derivative` which
-    /// steps the recursion forward.
-    fn calc_derivative_against(&self, input_node_name: &str) -> f64;
-
-    /// Updates weights of input nodes (if any) based on `gradient` and input node value.
-    ///
-    /// Then, recurse for each child input node with the `gradient` parameter set to
-    /// d(loss) / d(child input node activation output).
-    ///
-    /// `gradient` represents the value of d(loss) / d(node activation output).
-    /// (i.e., how much the loss will change if this node's output were to change by some small value d)
-    fn update_weights(&mut self, step_size: f64) {
-        // default to no weights to update
+    /// Whether the weights of connections into this node are used and trained.
+    fn is_weighted(&self) -> bool {
+        true
     }
 
-    /// Get a list of nodes connected as inputs of this node.
-    fn input_nodes(&self) -> Vec<AM<dyn Node + Send>>;
-
-    fn input_node_weights(&self) -> AM<HashMap<String, NodeWeight>>;
-
-    /// Get a list of nodes receiving this node's output as a parameter.
-    /// Used for calculating the derivative of all the nodes recursively.
-    /// No mutability needed, hopefully!
-    fn output_nodes(&self) -> &Vec<AM<dyn Node + Send>>;
-
-    /// Register a node as an input for this node
-    /// Do not call this function on its own, use the `connect` function instead
-    fn add_input_node(&mut self, input_node: AM<dyn Node + Send>);
-
-    /// Register a node as an input for this node with a predefined weight
-    /// Do not call this function on its own, use the `connect` function instead
-    fn add_input_node_init(&mut self, input_node: AM<dyn Node + Send>, weight: f64);
-
-    /// Register a node as a receiver of the output from this node
-    /// Do not call this function on its own, use the `connect` function instead
-    fn add_output_node(&mut self, output_node: AM<dyn Node + Send>);
-}
-
-/// Connect the output of node a to the input of node b.
-///
-/// Note the following method is required as it is not possible to assign references
-/// from A to B and from B to A simultaneously when in the scope of either A or B.
-/// Hence, a function outside the scope of A or B's `self` is required as only then
-/// can A and B reference each other.
-pub fn connect(a: AM<dyn Node + Send>, b: AM<dyn Node + Send>) {
-    a.lock().unwrap().add_output_node(b.clone());
-    b.lock().unwrap().add_input_node(a);
-}
+    /// Whether this node outputs a constant value, i.e. connections from it are biases.
+    fn is_constant(&self) -> bool {
+        false
+    }
 
-/// Connect the output of node a to the input of node b with a preset weight.
-pub fn connect_init(a: AM<dyn Node + Send>, b: AM<dyn Node + Send>, weight: f64) {
-    a.lock().unwrap().add_output_node(b.clone());
-    b.lock().unwrap().add_input_node_init(a, weight);
+    /// Assigns the value of an input node, e.g. from `InputLayer.set_input_values()`
+    fn set_value(&mut self, _value: f64) {
+        panic!("[{}] is not an input node!", self.name());
+    }
 }
 
 /// Contains stateful data used by all nodes during training
-struct TrainingState {
+pub struct TrainingState {
     /// The value of `DerivativeCalculationParams.calc_derivative_iteration` when
-    /// `Node.calc_activation_derivative()` was last called.
-    calc_derivative_iteration: i32,
+    /// `Graph.calc_activation_derivatives()` last calculated the node's `dloss`.
+    pub calc_derivative_iteration: i32,
     /// The last value of d(loss)/d(this activation) as calculated by
-    /// `Node.calc_activation_derivative()`.
-    dloss: f64,
+    /// `Graph.calc_activation_derivatives()`.
+    pub dloss: f64,
 }
 
 impl Default for TrainingState {
@@ -238,28 +335,19 @@ impl Default for TrainingState {
 pub struct InputNode {
     pub name: String,
     pub value: f64,
-    outputs: Vec<AM<dyn Node + Send>>,
     training_state: TrainingState,
-    /// Fighting borrow checker
-    empty_hashmap: AM<HashMap<String, NodeWeight>>,
 }
 
 impl InputNode {
-    pub fn new(_name: &str, value: f64) -> AM<InputNode> {
+    pub fn new(graph: &mut Graph, _name: &str, value: f64) -> NodeId {
         let name = _name.to_string();
         let node = InputNode {
             name,
             value,
-            outputs: vec![],
             training_state: Default::default(),
-            empty_hashmap: am(HashMap::new()),
         };
 
-        let node = am(node);
-
-        register_node(_name, node.clone());
-
-        node
+        graph.add_node(Box::new(node))
     }
 }
 
@@ -268,7 +356,11 @@ impl Node for InputNode {
         &self.name
     }
 
-    fn calc_activation(&mut self) -> f64 {
+    fn kind(&self) -> NodeKind {
+        NodeKind::Input(self.value)
+    }
+
+    fn calc_activation(&mut self, _inputs: &[NodeInput]) -> f64 {
         self.value
     }
 
@@ -284,32 +376,12 @@ impl Node for InputNode {
         &mut self.training_state
     }
 
-    fn calc_derivative_against(&self, input_node_name: &str) -> f64 {
-        panic!("Attempted to calculate derivative against an InputNode");
-    }
-
-    fn input_nodes(&self) -> Vec<AM<dyn Node + Send>> {
-        vec![]
-    }
-
-    fn input_node_weights(&self) -> AM<HashMap<String, NodeWeight>> {
-        self.empty_hashmap.clone()
-    }
-
-    fn output_nodes(&self) -> &Vec<AM<dyn Node + Send>> {
-        &self.outputs
-    }
-
-    fn add_input_node(&mut self, input_node: AM<dyn Node + Send>) {
-        panic!("InputNode does not have an input!")
-    }
-
-    fn add_input_node_init(&mut self, input_node: AM<dyn Node + Send>, weight: f64) {
-        panic!("InputNode does not have an input!")
+    fn calc_derivative_against(&self, _inputs: &[NodeInput], _input_index: usize) -> f64 {
+        panic!("Attempted to calculate derivative against an input of an InputNode");
     }
 
-    fn add_output_node(&mut self, node: AM<dyn Node + Send>) {
-        self.outputs.push(node);
+    fn set_value(&mut self, value: f64) {
+        self.value = value;
     }
 }
 
@@ -319,26 +391,18 @@ impl Node for InputNode {
 pub struct ConstantNode {
     pub name: String,
     pub const_value: f64,
-    outputs: Vec<AM<dyn Node + Send>>,
     training_state: TrainingState,
-    /// Fighting borrow checker
-    empty_hashmap: AM<HashMap<String, NodeWeight>>,
 }
 
 impl ConstantNode {
-    pub fn new(name: &str, const_value: f64) -> AM<ConstantNode> {
+    pub fn new(graph: &mut Graph, name: &str, const_value: f64) -> NodeId {
         let node = ConstantNode {
             name: name.to_string(),
             const_value,
-            outputs: vec![],
             training_state: Default::default(),
-            empty_hashmap: am(HashMap::new()),
         };
-        let node = am(node);
 
-        register_node(name, node.clone());
-
-        node
+        graph.add_node(Box::new(node))
     }
 }
 
@@ -347,7 +411,11 @@ impl Node for ConstantNode {
         &self.name
     }
 
-    fn calc_activation(&mut self) -> f64 {
+    fn kind(&self) -> NodeKind {
+        NodeKind::Constant(self.const_value)
+    }
+
+    fn calc_activation(&mut self, _inputs: &[NodeInput]) -> f64 {
         self.const_value
     }
 
@@ -363,56 +431,123 @@ impl Node for ConstantNode {
         &mut self.training_state
     }
 
-    fn calc_derivative_against(&self, input_node_name: &str) -> f64 {
-        panic!("Attempted to calculate derivative against a ConstantNode!");
+    fn calc_derivative_against(&self, _inputs: &[NodeInput], _input_index: usize) -> f64 {
+        panic!("Attempted to calculate derivative against an input of a ConstantNode!");
     }
 
-    fn input_nodes(&self) -> Vec<AM<dyn Node + Send>> {
-        vec![]
-    }
-
-    fn input_node_weights(&self) -> AM<HashMap<String, NodeWeight>> {
-        self.empty_hashmap.clone()
+    fn is_constant(&self) -> bool {
+        true
     }
+}
 
-    fn output_nodes(&self) -> &Vec<AM<dyn Node + Send>> {
-        &self.outputs
-    }
+/// A weighted connection into a node, stored by the `Graph` along with the
+/// other input connections of the receiving node.
+pub struct NodeWeight {
+    /// The input node
+    pub node: NodeId,
+    pub weight: f64,
+    /// False if the weight is yet to be drawn by an `Initializer`.
+    pub initialized: bool,
+    /// Sum of d(loss)/d(weight) accumulated by `Graph.accumulate_gradients()`
+    /// since the last weight update.
+    pub gradient: f64,
+    /// d(loss)/d(weight) of the last sample added to `gradient`, or 0 if the receiving node
+    /// wasn't reached by the last backward pass. Shown by `dot::to_dot()`, not saved in
+    /// checkpoints.
+    pub last_gradient: f64,
+    /// State kept by the `Optimizer` for this weight between updates.
+    pub optimizer_state: OptimizerState,
+}
 
-    fn add_input_node(&mut self, input_node: AM<dyn Node + Send>) {
-        panic!("ConstantNode does not have an input!")
+impl NodeWeight {
+    pub fn new(node: NodeId, weight: f64) -> NodeWeight {
+        NodeWeight {
+            node,
+            weight,
+            initialized: true,
+            gradient: 0.0,
+            last_gradient: 0.0,
+            optimizer_state: Default::default(),
+        }
     }
 
-    fn add_input_node_init(&mut self, input_node: AM<dyn Node + Send>, weight: f64) {
-        panic!("ConstantNode does not have an input!")
+    /// A connection whose weight will be drawn by `Graph.initialize_weights()`
+    pub fn uninitialized(node: NodeId) -> NodeWeight {
+        NodeWeight {
+            node,
+            weight: 0.0,
+            initialized: false,
+            gradient: 0.0,
+            last_gradient: 0.0,
+            optimizer_state: Default::default(),
+        }
     }
+}
 
-    fn add_output_node(&mut self, node: AM<dyn Node + Send>) {
-        self.outputs.push(node);
-    }
+/// Sums up all the products of each input-weight pair.
+///
+/// Shared by all nodes that calculate a weighted sum of their inputs.
+fn calc_weighted_sum(inputs: &[NodeInput]) -> f64 {
+    inputs
+        .iter()
+        .fold(0.0, |acc, input| acc + input.activation * input.weight)
 }
 
-pub struct NodeWeight {
-    pub node: AM<dyn Node + Send>,
-    pub weight: f64,
+/// d(actv_bar)/d(weight) of the input at `input_index` of a node that calculates a weighted
+/// sum of its inputs, to be multiplied by d(actv)/d(actv_bar) of the node.
+fn calc_weighted_sum_derivative_against_weight(inputs: &[NodeInput], input_index: usize) -> f64 {
+    /*
+        let loss     --> loss score
+            actv     --> activation of this node
+            actv_bar --> activation of this node before passing through the activation function
+                         (in the SumNode, the activation function is the identity function)
+                         this is also known as the "weighted sum"
+            weight   --> weight multiplier of an input node
+
+        d(loss)/d(weight) = d(loss)/d(actv) * d(actv)/d(actv_bar) * d(actv_bar)/d(weight)
+
+        d(loss)/d(actv) is the node's `TrainingState.dloss`
+        d(actv)/d(actv_bar) is the derivative of the activation function of the node.
+        d(actv_bar)/d(weight) is the activation value of the input node,
+                              since actv_bar = input * weight,
+                              d(actv_bar)/d(weight) = input
+
+    */
+
+    inputs[input_index].activation
 }
 
-impl NodeWeight {
-    pub fn new(node: AM<dyn Node + Send>, weight: f64) -> NodeWeight {
-        NodeWeight { node, weight }
-    }
+/// Adjusts the weights of `inputs` with the `Optimizer` based on the mean of their
+/// accumulated gradients, scaled and clipped as per `params`, then resets the
+/// accumulated gradients.
+pub fn update_input_weights(inputs: &mut [NodeWeight], params: &WeightUpdateParams) {
+    let batch_size = params.batch_size.max(1) as f64;
+
+    for nw in inputs.iter_mut() {
+        let mut gradient = nw.gradient / batch_size * params.gradient_scale;
+
+        if let Some(clip_value) = params.clip_value {
+            if gradient.abs() > clip_value {
+                gradient = gradient.signum() * clip_value;
+                params
+                    .clipped_gradients
+                    .set(params.clipped_gradients.get() + 1);
+            }
+        }
 
-    pub fn calc_weighted_activation(&self) -> f64 {
-        self.node.lock().unwrap().calc_activation() * self.weight
+        params.optimizer.update(
+            &mut nw.weight,
+            gradient,
+            params.step_size,
+            &mut nw.optimizer_state,
+        );
+        nw.gradient = 0.0;
     }
 }
 
 /// Sums up all the products of each input-weight pair
 pub struct SumNode {
     pub name: String,
-    /// Node name: NodeWeight
-    pub inputs: AM<HashMap<String, NodeWeight>>,
-    outputs: Vec<AM<dyn Node + Send>>,
     /// Stores the last value returned by `calc_activation()`.
     /// Only updated when `calc_activation()` is called.
     activation: f64,
@@ -420,20 +555,14 @@ pub struct SumNode {
 }
 
 impl SumNode {
-    pub fn new(name: &str) -> AM<SumNode> {
+    pub fn new(graph: &mut Graph, name: &str) -> NodeId {
         let node = SumNode {
             name: name.to_string(),
-            inputs: am(HashMap::new()),
-            outputs: vec![],
             activation: 0.0,
             training_state: Default::default(),
         };
 
-        let node = am(node);
-
-        register_node(name, node.clone());
-
-        node
+        graph.add_node(Box::new(node))
     }
 }
 
@@ -442,15 +571,12 @@ impl Node for SumNode {
         &self.name
     }
 
-    fn calc_activation(&mut self) -> f64 {
-        let sum = self
-            .inputs
-            .lock()
-            .unwrap()
-            .iter()
-            .fold(0.0, |acc, (name, node_weight)| {
-                acc + node_weight.calc_weighted_activation()
-            });
+    fn kind(&self) -> NodeKind {
+        NodeKind::Sum
+    }
+
+    fn calc_activation(&mut self, inputs: &[NodeInput]) -> f64 {
+        let sum = calc_weighted_sum(inputs);
 
         self.activation = sum;
 
@@ -469,93 +595,17 @@ impl Node for SumNode {
         &mut self.training_state
     }
 
-    fn calc_derivative_against(&self, input_node_name: &str) -> f64 {
+    fn calc_derivative_against(&self, inputs: &[NodeInput], input_index: usize) -> f64 {
         // since there is no activation function, derivative is just
         // d(weight * input_node activation) / d(input_node activation), i.e. just weight.
 
-        self.inputs
-            .lock()
-            .unwrap()
-            .get(input_node_name)
-            .expect(format!("[{}] is not an input of [{}]", input_node_name, self.name).as_str())
-            .weight
-    }
-
-    /// Updates weights of input nodes (if any) based on the previously calculated dloss.
-    /// `step_size` represents the multiplier of the dloss derivative to adjust the weight by.
-    fn update_weights(&mut self, step_size: f64) {
-        /*
-            let loss     --> loss score
-                actv     --> activation of this node
-                actv_bar --> activation of this node before passing through the activation function
-                             (in the SumNode, the activation function is the identity function)
-                             this is also known as the "weighted sum"
-                weight   --> weight multiplier of an input node
-
-            d(loss)/d(weight) = d(loss)/d(actv) * d(actv)/d(actv_bar) * d(actv_bar)/d(weight)
-
-            d(loss)/d(actv) is already given as `dloss_dactv`
-            d(actv)/d(actv_bar) is 1 as there is no activation function for the simple sum node.
-                                the derivative of f(x) = x is 1.
-            d(actv_bar)/d(weight) is the activation value of the input node,
-                                  since actv_bar = input * weight,
-                                  d(actv_bar)/d(weight) = input
-
-        */
-
-        let dloss_dactv = self.training_state.dloss;
-        let dactv_dactv_bar = 1.0; // f(x) = x ==> f'(x) = 1, identity activation function
-
-        let mut inputs_dloss = vec![];
-
-        let mut inputs = self.inputs.lock().unwrap();
-        for k in inputs.keys() {
-            let mut nw = inputs.get(k).unwrap();
-            let dactv_bar_weight = nw.node.lock().unwrap().get_last_calc_activation();
-
-            let dloss_dweight = dloss_dactv * dactv_dactv_bar * dactv_bar_weight;
-
-            inputs_dloss.push((k.to_owned(), dloss_dweight));
-        }
-
-        for (i, dloss) in inputs_dloss.into_iter() {
-            inputs.get_mut(i.as_str()).unwrap().weight -= step_size * dloss;
-        }
-    }
-
-    fn input_nodes<'a>(&'a self) -> Vec<AM<dyn Node + Send>> {
-        self.inputs
-            .lock()
-            .unwrap()
-            .iter()
-            .map(|(_, x)| x.node.clone())
-            .collect()
-    }
-
-    fn input_node_weights(&self) -> AM<HashMap<String, NodeWeight>> {
-        self.inputs.clone()
-    }
-
-    fn output_nodes(&self) -> &Vec<AM<dyn Node + Send>> {
-        &self.outputs
+        inputs[input_index].weight
     }
 
-    /// Add an input with a randomly initialized weight ranging from -1 to 1
-    /// DO NOT CALL ALONE. Use `connect()` instead
-    fn add_input_node(&mut self, input_node: AM<dyn Node + Send>) {
-        self.add_input_node_init(input_node, thread_rng().gen_range(-1.0, 1.0));
-    }
-
-    fn add_input_node_init(&mut self, input_node: AM<dyn Node + Send>, weight: f64) {
-        let clone = input_node.clone();
-        self.inputs.lock().unwrap().insert(
-            clone.lock().unwrap().name().to_string(),
-            NodeWeight::new(input_node, weight),
-        );
-    }
+    fn calc_derivative_against_weight(&self, inputs: &[NodeInput], input_index: usize) -> f64 {
+        let dactv_dactv_bar = 1.0; // f(x) = x ==> f'(x) = 1, identity activation function
 
-    fn add_output_node(&mut self, node: AM<dyn Node + Send>) {
-        self.outputs.push(node);
+        dactv_dactv_bar * calc_weighted_sum_derivative_against_weight(inputs, input_index)
     }
 }
 
@@ -563,26 +613,37 @@ impl Node for SumNode {
 /// the result through a sigmoid logistic function.
 pub struct SigmoidNode {
     pub name: String,
-    /// Node name: NodeWeight
-    pub inputs: AM<HashMap<String, NodeWeight>>,
-    outputs: Vec<AM<dyn Node + Send>>,
     /// Stores the last value returned by `calc_activation()`.
     /// Only updated when `calc_activation()` is called.
     activation: f64,
     training_state: TrainingState,
 }
 
+impl SigmoidNode {
+    pub fn new(graph: &mut Graph, name: &str) -> NodeId {
+        let node = SigmoidNode {
+            name: name.to_string(),
+            activation: 0.0,
+            training_state: Default::default(),
+        };
+
+        graph.add_node(Box::new(node))
+    }
+}
+
 impl Node for SigmoidNode {
     fn name(&self) -> &str {
         &self.name
     }
 
-    fn calc_activation(&mut self) -> f64 {
-        let sum = self.inputs.lock().unwrap().iter().fold(0.0, |acc, (_, x)| {
-            acc + x.node.lock().unwrap().calc_activation() * x.weight
-        });
+    fn kind(&self) -> NodeKind {
+        NodeKind::Sigmoid
+    }
 
-        let sigmoid_activation = 1.0 / (1.0 + f64::exp(-sum));
+    fn calc_activation(&mut self, inputs: &[NodeInput]) -> f64 {
+        let sum = calc_weighted_sum(inputs);
+
+        let sigmoid_activation = Activation::Sigmoid.value(sum);
 
         self.activation = sigmoid_activation;
 
@@ -601,7 +662,7 @@ impl Node for SigmoidNode {
         &mut self.training_state
     }
 
-    fn calc_derivative_against(&self, input_node_name: &str) -> f64 {
+    fn calc_derivative_against(&self, inputs: &[NodeInput], input_index: usize) -> f64 {
         // let z -> input_node activation * connection weight
         // hence, dz/d(input activation) = w
         // let a -> sigmoid(z)
@@ -609,55 +670,96 @@ impl Node for SigmoidNode {
         // d(a) / d(input_node activation) = d(a)/d(z) * d(z)/d(input_node activation)
         //                                 = sigmoid(z)(1 - sigmoid(z)) * connection weight
 
-        let w = self
-            .inputs
-            .lock()
-            .unwrap()
-            .get(input_node_name)
-            .expect(format!("[{}] is not an input of [{}]", input_node_name, self.name).as_str())
-            .weight;
+        let w = inputs[input_index].weight;
 
         let a = self.get_last_calc_activation();
 
         a * (1.0 - a) * w
     }
 
-    fn update_weights(&mut self, step_size: f64) {
-        unimplemented!();
+    fn calc_derivative_against_weight(&self, inputs: &[NodeInput], input_index: usize) -> f64 {
+        // actv = sigmoid(actv_bar)
+        // d(actv)/d(actv_bar) = sigmoid(actv_bar)(1 - sigmoid(actv_bar))
+        //                     = actv(1 - actv)
+
+        let a = self.activation;
+        let dactv_dactv_bar = a * (1.0 - a);
+
+        dactv_dactv_bar * calc_weighted_sum_derivative_against_weight(inputs, input_index)
+    }
+}
+
+/// Sums up all the products of each input-weight pair and passes
+/// the result through an `Activation` function.
+///
+/// With `Activation::Identity`, this behaves the same as a `SumNode`, and with
+/// `Activation::Sigmoid`, the same as a `SigmoidNode`.
+pub struct ActivationNode {
+    pub name: String,
+    pub activation_fn: Activation,
+    /// Stores the weighted sum of the inputs calculated in the last `calc_activation()` call,
+    /// i.e. the value before being passed through the activation function.
+    weighted_sum: f64,
+    /// Stores the last value returned by `calc_activation()`.
+    /// Only updated when `calc_activation()` is called.
+    activation: f64,
+    training_state: TrainingState,
+}
+
+impl ActivationNode {
+    pub fn new(graph: &mut Graph, name: &str, activation_fn: Activation) -> NodeId {
+        let node = ActivationNode {
+            name: name.to_string(),
+            activation_fn,
+            weighted_sum: 0.0,
+            activation: 0.0,
+            training_state: Default::default(),
+        };
+
+        graph.add_node(Box::new(node))
+    }
+}
+
+impl Node for ActivationNode {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> NodeKind {
+        NodeKind::Activation(self.activation_fn)
     }
 
-    fn input_nodes(&self) -> Vec<AM<dyn Node + Send>> {
-        self.inputs
-            .lock()
-            .unwrap()
-            .iter()
-            .map(|(_, x)| x.node.clone())
-            .collect()
+    fn calc_activation(&mut self, inputs: &[NodeInput]) -> f64 {
+        self.weighted_sum = calc_weighted_sum(inputs);
+        self.activation = self.activation_fn.value(self.weighted_sum);
+
+        self.activation
     }
 
-    fn input_node_weights(&self) -> AM<HashMap<String, NodeWeight>> {
-        self.inputs.clone()
+    fn get_last_calc_activation(&self) -> f64 {
+        self.activation
     }
 
-    fn output_nodes(&self) -> &Vec<AM<dyn Node + Send>> {
-        &self.outputs
+    fn get_training_state(&self) -> &TrainingState {
+        &self.training_state
     }
 
-    /// Add an input with a randomly initialized weight ranging from -1 to 1
-    /// DO NOT CALL ALONE. Use `connect()` instead
-    fn add_input_node(&mut self, input_node: AM<dyn Node + Send>) {
-        self.add_input_node_init(input_node, thread_rng().gen_range(-1.0, 1.0));
+    fn get_training_state_mut(&mut self) -> &mut TrainingState {
+        &mut self.training_state
     }
 
-    fn add_input_node_init(&mut self, input_node: AM<dyn Node + Send>, weight: f64) {
-        let clone = input_node.clone();
-        self.inputs.lock().unwrap().insert(
-            clone.lock().unwrap().name().to_string(),
-            NodeWeight::new(input_node, weight),
-        );
+    fn calc_derivative_against(&self, inputs: &[NodeInput], input_index: usize) -> f64 {
+        // d(actv) / d(input_node activation) = d(actv)/d(actv_bar) * d(actv_bar)/d(input_node activation)
+        //                                    = f'(actv_bar) * connection weight
+
+        let w = inputs[input_index].weight;
+
+        self.activation_fn.derivative(self.weighted_sum) * w
     }
 
-    fn add_output_node(&mut self, node: AM<dyn Node + Send>) {
-        self.outputs.push(node);
+    fn calc_derivative_against_weight(&self, inputs: &[NodeInput], input_index: usize) -> f64 {
+        let dactv_dactv_bar = self.activation_fn.derivative(self.weighted_sum);
+
+        dactv_dactv_bar * calc_weighted_sum_derivative_against_weight(inputs, input_index)
     }
 }
@@ -0,0 +1,242 @@
+//!
+//! Optimizers which decide how weights are adjusted based on their gradients
+//!
+
+/// State kept for each weight between updates, stored in `NodeWeight.optimizer_state`.
+///
+/// Each optimizer only uses the fields it needs.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OptimizerState {
+    /// Velocity of the weight, used by `Momentum` and `Nesterov`
+    pub velocity: f64,
+    /// Running average of the gradient, used by `Adam` and `AdamW`
+    pub first_moment: f64,
+    /// Running average of the squared gradient, used by `RmsProp`, `Adam` and `AdamW`,
+    /// or the sum of all squared gradients for `AdaGrad`
+    pub second_moment: f64,
+    /// Number of updates applied to the weight, used for bias correction
+    pub step: u64,
+}
+
+/// Decides how a weight is adjusted given the mean gradient of a batch.
+pub trait Optimizer {
+    /// Adjust `weight` based on `gradient`, i.e. d(loss)/d(weight) averaged over the batch.
+    ///
+    /// `learning_rate` is the step size given by `NetworkConfigs.learning_rate`, and `state`
+    /// is the state kept for this particular weight.
+    fn update(
+        &self,
+        weight: &mut f64,
+        gradient: f64,
+        learning_rate: f64,
+        state: &mut OptimizerState,
+    );
+}
+
+/// Plain stochastic gradient descent: weight -= learning_rate * gradient
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sgd;
+
+impl Optimizer for Sgd {
+    fn update(&self, weight: &mut f64, gradient: f64, learning_rate: f64, _: &mut OptimizerState) {
+        *weight -= learning_rate * gradient;
+    }
+}
+
+/// Gradient descent with momentum, which accumulates a velocity in directions of
+/// consistent gradients.
+#[derive(Debug, Clone, Copy)]
+pub struct Momentum {
+    /// Fraction of the velocity kept every update. Default: 0.9
+    pub momentum: f64,
+}
+
+impl Default for Momentum {
+    fn default() -> Momentum {
+        Momentum { momentum: 0.9 }
+    }
+}
+
+impl Optimizer for Momentum {
+    fn update(
+        &self,
+        weight: &mut f64,
+        gradient: f64,
+        learning_rate: f64,
+        state: &mut OptimizerState,
+    ) {
+        state.velocity = self.momentum * state.velocity - learning_rate * gradient;
+        *weight += state.velocity;
+    }
+}
+
+/// Nesterov accelerated gradient, a variant of `Momentum` which corrects the velocity
+/// using the gradient at the look-ahead position.
+#[derive(Debug, Clone, Copy)]
+pub struct Nesterov {
+    /// Fraction of the velocity kept every update. Default: 0.9
+    pub momentum: f64,
+}
+
+impl Default for Nesterov {
+    fn default() -> Nesterov {
+        Nesterov { momentum: 0.9 }
+    }
+}
+
+impl Optimizer for Nesterov {
+    fn update(
+        &self,
+        weight: &mut f64,
+        gradient: f64,
+        learning_rate: f64,
+        state: &mut OptimizerState,
+    ) {
+        // Reformulated so that the gradient is taken at the current weight instead of
+        // the look-ahead weight, see Sutskever et al. (2013)
+        let prev_velocity = state.velocity;
+        state.velocity = self.momentum * state.velocity - learning_rate * gradient;
+        *weight += -self.momentum * prev_velocity + (1.0 + self.momentum) * state.velocity;
+    }
+}
+
+/// Scales the learning rate of each weight down by the root of the sum of all its
+/// squared gradients so far.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaGrad {
+    /// Added to the denominator to prevent division by zero. Default: 1e-8
+    pub epsilon: f64,
+}
+
+impl Default for AdaGrad {
+    fn default() -> AdaGrad {
+        AdaGrad { epsilon: 1e-8 }
+    }
+}
+
+impl Optimizer for AdaGrad {
+    fn update(
+        &self,
+        weight: &mut f64,
+        gradient: f64,
+        learning_rate: f64,
+        state: &mut OptimizerState,
+    ) {
+        state.second_moment += gradient * gradient;
+        *weight -= learning_rate * gradient / (state.second_moment.sqrt() + self.epsilon);
+    }
+}
+
+/// Scales the learning rate of each weight down by the root of a running average
+/// of its squared gradients.
+#[derive(Debug, Clone, Copy)]
+pub struct RmsProp {
+    /// Decay rate of the running average. Default: 0.9
+    pub decay: f64,
+    /// Added to the denominator to prevent division by zero. Default: 1e-8
+    pub epsilon: f64,
+}
+
+impl Default for RmsProp {
+    fn default() -> RmsProp {
+        RmsProp {
+            decay: 0.9,
+            epsilon: 1e-8,
+        }
+    }
+}
+
+impl Optimizer for RmsProp {
+    fn update(
+        &self,
+        weight: &mut f64,
+        gradient: f64,
+        learning_rate: f64,
+        state: &mut OptimizerState,
+    ) {
+        state.second_moment =
+            self.decay * state.second_moment + (1.0 - self.decay) * gradient * gradient;
+        *weight -= learning_rate * gradient / (state.second_moment.sqrt() + self.epsilon);
+    }
+}
+
+/// Adaptive moment estimation, which keeps bias-corrected running averages
+/// of each weight's gradient and squared gradient.
+#[derive(Debug, Clone, Copy)]
+pub struct Adam {
+    /// Decay rate of the first moment. Default: 0.9
+    pub beta1: f64,
+    /// Decay rate of the second moment. Default: 0.999
+    pub beta2: f64,
+    /// Added to the denominator to prevent division by zero. Default: 1e-8
+    pub epsilon: f64,
+}
+
+impl Default for Adam {
+    fn default() -> Adam {
+        Adam {
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn update(
+        &self,
+        weight: &mut f64,
+        gradient: f64,
+        learning_rate: f64,
+        state: &mut OptimizerState,
+    ) {
+        *weight -= learning_rate * adam_step(self, gradient, state);
+    }
+}
+
+/// `Adam` with decoupled weight decay, which shrinks weights directly instead of
+/// through the gradient.
+#[derive(Debug, Clone, Copy)]
+pub struct AdamW {
+    pub adam: Adam,
+    /// Fraction of the weight removed every update, scaled by the learning rate.
+    /// Default: 0.01
+    pub weight_decay: f64,
+}
+
+impl Default for AdamW {
+    fn default() -> AdamW {
+        AdamW {
+            adam: Default::default(),
+            weight_decay: 0.01,
+        }
+    }
+}
+
+impl Optimizer for AdamW {
+    fn update(
+        &self,
+        weight: &mut f64,
+        gradient: f64,
+        learning_rate: f64,
+        state: &mut OptimizerState,
+    ) {
+        let decay = self.weight_decay * *weight;
+        *weight -= learning_rate * (adam_step(&self.adam, gradient, state) + decay);
+    }
+}
+
+/// Updates the moments in `state` and returns the bias-corrected Adam step direction,
+/// which is to be multiplied by the learning rate.
+fn adam_step(adam: &Adam, gradient: f64, state: &mut OptimizerState) -> f64 {
+    state.step += 1;
+    state.first_moment = adam.beta1 * state.first_moment + (1.0 - adam.beta1) * gradient;
+    state.second_moment =
+        adam.beta2 * state.second_moment + (1.0 - adam.beta2) * gradient * gradient;
+
+    let t = state.step as i32;
+    let first_moment = state.first_moment / (1.0 - adam.beta1.powi(t));
+    let second_moment = state.second_moment / (1.0 - adam.beta2.powi(t));
+
+    first_moment / (second_moment.sqrt() + adam.epsilon)
+}
@@ -0,0 +1,53 @@
+//!
+//! Weight penalties which discourage large weights
+//!
+
+/// Penalty on the weight of a connection, which is added to the loss and whose
+/// derivative is added to d(loss)/d(weight).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Regularizer {
+    /// No penalty
+    #[default]
+    None,
+    /// Lasso: lambda * |w|. Pushes weights towards exactly 0.
+    L1(f64),
+    /// Ridge / weight decay: lambda * w^2
+    L2(f64),
+    /// Elastic net: l1 * |w| + l2 * w^2
+    ElasticNet { l1: f64, l2: f64 },
+}
+
+impl Regularizer {
+    /// The penalty of one weight
+    pub fn penalty(&self, weight: f64) -> f64 {
+        match *self {
+            Regularizer::None => 0.0,
+            Regularizer::L1(lambda) => lambda * weight.abs(),
+            Regularizer::L2(lambda) => lambda * weight * weight,
+            Regularizer::ElasticNet { l1, l2 } => l1 * weight.abs() + l2 * weight * weight,
+        }
+    }
+
+    /// d(penalty)/d(weight) of one weight.
+    ///
+    /// The subgradient of |w| at w = 0 is taken to be 0.
+    pub fn gradient(&self, weight: f64) -> f64 {
+        match *self {
+            Regularizer::None => 0.0,
+            Regularizer::L1(lambda) => lambda * sign(weight),
+            Regularizer::L2(lambda) => 2.0 * lambda * weight,
+            Regularizer::ElasticNet { l1, l2 } => l1 * sign(weight) + 2.0 * l2 * weight,
+        }
+    }
+}
+
+/// Unlike `f64::signum()`, returns 0 for 0
+pub(crate) fn sign(x: f64) -> f64 {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
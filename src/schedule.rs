@@ -0,0 +1,217 @@
+//!
+//! Learning rate schedules which vary the learning rate over the course of training
+//!
+
+use std::f64;
+use std::io;
+
+/// Decides the learning rate used for each weight update.
+///
+/// `base_learning_rate` passed to the methods below is `NetworkConfigs.learning_rate`.
+pub trait LearningRateSchedule {
+    /// The learning rate to use for the next weight update.
+    ///
+    /// `step` is the number of weight updates done so far, and `epoch` is the number of
+    /// epochs trained so far.
+    fn learning_rate(&self, base_learning_rate: f64, step: usize, epoch: usize) -> f64;
+
+    /// Called by `Network.train_one_epoch()` at the end of every epoch.
+    ///
+    /// `loss` is the validation loss if the network has validation data,
+    /// otherwise the mean training loss of the epoch.
+    fn on_epoch_end(&mut self, _epoch: usize, _loss: f64) {}
+
+    /// Any state changed by `on_epoch_end()`, which is stored in checkpoints so that training
+    /// can be resumed with the same learning rates. Empty for stateless schedules.
+    fn state(&self) -> Vec<f64> {
+        vec![]
+    }
+
+    /// Restores the state returned by `state()` when resuming from a checkpoint.
+    ///
+    /// Returns an error if `state` doesn't have as many values as `state()` returns.
+    fn set_state(&mut self, state: &[f64]) -> io::Result<()> {
+        check_state_len(state, 0)
+    }
+}
+
+/// Always uses the base learning rate
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Constant;
+
+impl LearningRateSchedule for Constant {
+    fn learning_rate(&self, base_learning_rate: f64, _: usize, _: usize) -> f64 {
+        base_learning_rate
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every `epochs_per_step` epochs.
+#[derive(Debug, Clone, Copy)]
+pub struct StepDecay {
+    pub epochs_per_step: usize,
+    pub gamma: f64,
+}
+
+impl LearningRateSchedule for StepDecay {
+    fn learning_rate(&self, base_learning_rate: f64, _: usize, epoch: usize) -> f64 {
+        let steps = epoch / self.epochs_per_step.max(1);
+        base_learning_rate * self.gamma.powi(steps as i32)
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every epoch.
+#[derive(Debug, Clone, Copy)]
+pub struct ExponentialDecay {
+    pub gamma: f64,
+}
+
+impl LearningRateSchedule for ExponentialDecay {
+    fn learning_rate(&self, base_learning_rate: f64, _: usize, epoch: usize) -> f64 {
+        base_learning_rate * self.gamma.powi(epoch as i32)
+    }
+}
+
+/// Cosine annealing with warm restarts (SGDR).
+///
+/// The learning rate follows half a cosine wave from the base learning rate down to
+/// `min_learning_rate` over `period` epochs, then restarts at the base learning rate.
+/// Each subsequent period is `period_mult` times longer than the previous one.
+#[derive(Debug, Clone, Copy)]
+pub struct CosineAnnealingWarmRestarts {
+    /// Number of epochs of the first period
+    pub period: usize,
+    pub period_mult: usize,
+    pub min_learning_rate: f64,
+}
+
+impl LearningRateSchedule for CosineAnnealingWarmRestarts {
+    fn learning_rate(&self, base_learning_rate: f64, _: usize, epoch: usize) -> f64 {
+        // Find the number of epochs since the last restart, and the length of the current period
+        let mut epochs_since_restart = epoch;
+        let mut period = self.period.max(1);
+        while epochs_since_restart >= period {
+            epochs_since_restart -= period;
+            period *= self.period_mult.max(1);
+        }
+
+        let progress = epochs_since_restart as f64 / period as f64;
+
+        self.min_learning_rate
+            + 0.5
+                * (base_learning_rate - self.min_learning_rate)
+                * (1.0 + (f64::consts::PI * progress).cos())
+    }
+}
+
+/// Linearly increases the learning rate from 0 over the first `warmup_steps` weight updates,
+/// then follows the `after` schedule.
+pub struct LinearWarmup {
+    pub warmup_steps: usize,
+    pub after: Box<dyn LearningRateSchedule>,
+}
+
+impl LearningRateSchedule for LinearWarmup {
+    fn learning_rate(&self, base_learning_rate: f64, step: usize, epoch: usize) -> f64 {
+        let learning_rate = self.after.learning_rate(base_learning_rate, step, epoch);
+
+        if step < self.warmup_steps {
+            learning_rate * (step + 1) as f64 / self.warmup_steps as f64
+        } else {
+            learning_rate
+        }
+    }
+
+    fn on_epoch_end(&mut self, epoch: usize, loss: f64) {
+        self.after.on_epoch_end(epoch, loss);
+    }
+
+    fn state(&self) -> Vec<f64> {
+        self.after.state()
+    }
+
+    fn set_state(&mut self, state: &[f64]) -> io::Result<()> {
+        self.after.set_state(state)
+    }
+}
+
+/// Multiplies the learning rate by `factor` once the loss (preferably the validation loss)
+/// has not improved for more than `patience` epochs.
+#[derive(Debug, Clone, Copy)]
+pub struct ReduceOnPlateau {
+    pub factor: f64,
+    /// Number of epochs without improvement to tolerate before reducing the learning rate
+    pub patience: usize,
+    /// Minimum relative decrease of the loss that counts as an improvement
+    pub threshold: f64,
+    pub min_learning_rate: f64,
+    /// Lowest loss seen so far
+    best_loss: f64,
+    /// Number of epochs since the last improvement or reduction
+    bad_epochs: usize,
+    /// Product of all reductions so far
+    scale: f64,
+}
+
+impl ReduceOnPlateau {
+    pub fn new(factor: f64, patience: usize) -> ReduceOnPlateau {
+        ReduceOnPlateau {
+            factor,
+            patience,
+            threshold: 1e-4,
+            min_learning_rate: 0.0,
+            best_loss: f64::INFINITY,
+            bad_epochs: 0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl LearningRateSchedule for ReduceOnPlateau {
+    fn learning_rate(&self, base_learning_rate: f64, _: usize, _: usize) -> f64 {
+        (base_learning_rate * self.scale).max(self.min_learning_rate)
+    }
+
+    fn on_epoch_end(&mut self, _: usize, loss: f64) {
+        if loss < self.best_loss * (1.0 - self.threshold) {
+            self.best_loss = loss;
+            self.bad_epochs = 0;
+        } else {
+            self.bad_epochs += 1;
+
+            if self.bad_epochs > self.patience {
+                self.scale *= self.factor;
+                self.bad_epochs = 0;
+            }
+        }
+    }
+
+    fn state(&self) -> Vec<f64> {
+        vec![self.best_loss, self.bad_epochs as f64, self.scale]
+    }
+
+    fn set_state(&mut self, state: &[f64]) -> io::Result<()> {
+        check_state_len(state, 3)?;
+
+        self.best_loss = state[0];
+        self.bad_epochs = state[1] as usize;
+        self.scale = state[2];
+
+        Ok(())
+    }
+}
+
+/// Rejects the state of a different kind of schedule, e.g. in a checkpoint of another network
+fn check_state_len(state: &[f64], len: usize) -> io::Result<()> {
+    if state.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Expected {} values of learning rate schedule state, found {}",
+                len,
+                state.len()
+            ),
+        ));
+    }
+
+    Ok(())
+}
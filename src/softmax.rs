@@ -0,0 +1,201 @@
+//!
+//! Softmax across a group of nodes, for multi-class classification
+//!
+
+use graph::{Graph, NodeId};
+use node::{Node, NodeInput, NodeKind, TrainingState};
+use std::f64;
+
+/// Normalises the activations of a set of logit nodes (e.g. `SumNode`s) into a probability
+/// distribution with a numerically stable softmax.
+///
+/// The group consists of one `SoftmaxNode` per logit, named `{name}_{i}`, each of which takes
+/// every logit as an input so that the full Jacobian is backpropagated. Use `nodes` as the
+/// nodes of the `OutputLayer`.
+///
+/// ```
+/// # use neural_network::graph::Graph;
+/// # use neural_network::layers::OutputLayer;
+/// # use neural_network::loss::CategoricalCrossEntropy;
+/// # use neural_network::node::SumNode;
+/// # use neural_network::softmax::SoftmaxGroup;
+/// # let mut graph = Graph::new();
+/// # let logits = vec![SumNode::new(&mut graph, "l1"), SumNode::new(&mut graph, "l2")];
+/// # let ground_truths = vec![1.0, 0.0];
+/// let softmax = SoftmaxGroup::new(&mut graph, "softmax", &logits);
+/// let output_layer = OutputLayer::new(
+///     &softmax.nodes,
+///     &ground_truths,
+///     Box::new(CategoricalCrossEntropy::default()),
+/// );
+/// ```
+pub struct SoftmaxGroup {
+    /// The nodes of this group in the same order as the logits
+    pub nodes: Vec<NodeId>,
+}
+
+impl SoftmaxGroup {
+    /// Creates a softmax over `logits`, which backpropagates through the full Jacobian
+    /// d(p_i) / d(z_j) = p_i * (δij - p_j).
+    /// Works with any `Loss`.
+    pub fn new(graph: &mut Graph, name: &str, logits: &[NodeId]) -> SoftmaxGroup {
+        SoftmaxGroup::create(graph, name, logits, false)
+    }
+
+    /// Creates a softmax over `logits` which is fused with `SoftmaxCrossEntropy`.
+    ///
+    /// For categorical cross-entropy, d(loss) / d(z_i) simplifies to p_i - y_i, which
+    /// `SoftmaxCrossEntropy` gives directly. The nodes of this group then pass that gradient
+    /// straight through to their own logit, skipping the Jacobian which is more expensive
+    /// and loses precision when p_i is close to 0.
+    ///
+    /// Must be used with `SoftmaxCrossEntropy` as the `Loss` of the `OutputLayer`.
+    pub fn fused_with_cross_entropy(
+        graph: &mut Graph,
+        name: &str,
+        logits: &[NodeId],
+    ) -> SoftmaxGroup {
+        SoftmaxGroup::create(graph, name, logits, true)
+    }
+
+    fn create(
+        graph: &mut Graph,
+        name: &str,
+        logits: &[NodeId],
+        fused_cross_entropy: bool,
+    ) -> SoftmaxGroup {
+        assert!(
+            !logits.is_empty(),
+            "SoftmaxGroup must have at least 1 logit!"
+        );
+
+        let mut nodes = vec![];
+
+        for (idx, &logit) in logits.iter().enumerate() {
+            let node = SoftmaxNode::new(
+                graph,
+                &format!("{}_{}", name, idx),
+                logit,
+                fused_cross_entropy,
+            );
+
+            for &logit in logits {
+                graph.connect(logit, node);
+            }
+
+            nodes.push(node);
+        }
+
+        SoftmaxGroup { nodes }
+    }
+}
+
+/// One output of a `SoftmaxGroup`: p_i = e^(z_i) / sum(e^(z_j)) where z_i is this node's
+/// own logit, and z_j are all the logits of the group.
+///
+/// Connections into this node are unweighted, i.e. their weights are ignored and
+/// not trained.
+pub struct SoftmaxNode {
+    pub name: String,
+    /// The logit z_i whose probability this node outputs
+    pub logit: NodeId,
+    /// Passes d(loss) / d(activation) straight through to the own logit instead of
+    /// using the Jacobian, see `SoftmaxGroup::fused_with_cross_entropy()`.
+    pub fused_cross_entropy: bool,
+    /// Probability of each input logit as of the last `calc_activation()`, in input order
+    probabilities: Vec<f64>,
+    /// Stores the last value returned by `calc_activation()`.
+    /// Only updated when `calc_activation()` is called.
+    activation: f64,
+    training_state: TrainingState,
+}
+
+impl SoftmaxNode {
+    /// Use `SoftmaxGroup` instead, which creates and connects the nodes of the whole group.
+    pub fn new(graph: &mut Graph, name: &str, logit: NodeId, fused_cross_entropy: bool) -> NodeId {
+        let node = SoftmaxNode {
+            name: name.to_string(),
+            logit,
+            fused_cross_entropy,
+            probabilities: vec![],
+            activation: 0.0,
+            training_state: Default::default(),
+        };
+
+        graph.add_node(Box::new(node))
+    }
+}
+
+impl Node for SoftmaxNode {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> NodeKind {
+        NodeKind::Softmax {
+            logit: self.logit,
+            fused_cross_entropy: self.fused_cross_entropy,
+        }
+    }
+
+    fn calc_activation(&mut self, inputs: &[NodeInput]) -> f64 {
+        // Subtracting the largest logit doesn't change the result,
+        // but prevents e^z from overflowing.
+        let max = inputs
+            .iter()
+            .fold(f64::NEG_INFINITY, |max, input| max.max(input.activation));
+        let sum: f64 = inputs.iter().map(|x| (x.activation - max).exp()).sum();
+
+        self.probabilities = inputs
+            .iter()
+            .map(|x| (x.activation - max).exp() / sum)
+            .collect();
+
+        let own_index = inputs
+            .iter()
+            .position(|x| x.node == self.logit)
+            .unwrap_or_else(|| panic!("[{}] Own logit is not connected to this node!", self.name));
+
+        self.activation = self.probabilities[own_index];
+
+        self.activation
+    }
+
+    fn get_last_calc_activation(&self) -> f64 {
+        self.activation
+    }
+
+    fn get_training_state(&self) -> &TrainingState {
+        &self.training_state
+    }
+
+    fn get_training_state_mut(&mut self) -> &mut TrainingState {
+        &mut self.training_state
+    }
+
+    fn calc_derivative_against(&self, inputs: &[NodeInput], input_index: usize) -> f64 {
+        let is_own_logit = inputs[input_index].node == self.logit;
+
+        if self.fused_cross_entropy {
+            // d(loss) / d(activation) is already d(loss) / d(logit), see SoftmaxCrossEntropy
+            return if is_own_logit { 1.0 } else { 0.0 };
+        }
+
+        /*
+            Let p_i -> this activation, z_i -> own logit, z_j -> input_node logit
+
+            d(p_i) / d(z_j) = p_i * (1 - p_j)   if i == j
+                            = -p_i * p_j        otherwise
+        */
+        let p_j = self.probabilities[input_index];
+
+        let kronecker_delta = if is_own_logit { 1.0 } else { 0.0 };
+
+        self.activation * (kronecker_delta - p_j)
+    }
+
+    fn is_weighted(&self) -> bool {
+        // connections into a softmax are not weighted
+        false
+    }
+}
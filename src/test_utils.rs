@@ -0,0 +1,67 @@
+//!
+//! Networks and helpers shared by the tests of several modules
+//!
+
+use graph::Graph;
+use layers::{InputLayer, OutputLayer};
+use loss::SoftmaxCrossEntropy;
+use network::{Network, NetworkConfigs};
+use node::{ConstantNode, InputNode, SumNode};
+use optimizer::Adam;
+use schedule::ReduceOnPlateau;
+use softmax::SoftmaxGroup;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+/// A softmax classifier over two logits "l1" and "l2" of the inputs "i1" and "i2" and a
+/// bias, with a removed node in between, trained with state in the optimizer, the learning
+/// rate schedule and the shuffling
+pub fn softmax_network() -> Network {
+    let mut graph = Graph::new();
+
+    let i1 = InputNode::new(&mut graph, "i1", 0.0);
+    let i2 = InputNode::new(&mut graph, "i2", 0.0);
+    let bias = ConstantNode::new(&mut graph, "bias", 1.0);
+    let removed = SumNode::new(&mut graph, "removed");
+    let l1 = SumNode::new(&mut graph, "l1");
+    let l2 = SumNode::new(&mut graph, "l2");
+
+    graph.connect(i1, removed);
+    graph.connect(removed, l1);
+    for &logit in &[l1, l2] {
+        for &input in &[i1, i2, bias] {
+            graph.connect(input, logit);
+        }
+    }
+    graph.remove_node("removed");
+
+    let softmax = SoftmaxGroup::fused_with_cross_entropy(&mut graph, "softmax", &[l1, l2]);
+
+    let input_layer = InputLayer::new(&[i1, i2], &[0.3, -1.2, 2.5, 0.7, -0.4, 1.1, 1.8, -2.0]);
+    let output_layer = OutputLayer::new(
+        &softmax.nodes,
+        &[1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0],
+        Box::new(SoftmaxCrossEntropy::default()),
+    );
+
+    let network_configs = NetworkConfigs {
+        seed: 7,
+        shuffle: true,
+        batch_size: 3,
+        learning_rate: 0.1,
+        optimizer: Box::new(Adam::default()),
+        learning_rate_schedule: Box::new(ReduceOnPlateau::new(0.5, 0)),
+        ..Default::default()
+    };
+
+    let mut network = Network::with_configs(graph, input_layer, output_layer, network_configs);
+    // Flipped labels, so that the validation loss rises and the learning rate is reduced
+    network.set_validation_data(&[0.3, -1.2, 2.5, 0.7], &[0.0, 1.0, 1.0, 0.0]);
+    network
+}
+
+/// A path in the temporary directory which tests running in parallel processes don't share
+pub fn temp_path(name: &str, extension: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}-{}.{}", name, process::id(), extension))
+}